@@ -1,12 +1,25 @@
+use super::diffr_lib::Algorithm;
+use super::terminal;
 use super::AppConfig;
+use super::ColorWhen;
+use super::FileHeaderStyle;
+use super::Granularity;
+use super::HtmlMode;
+use super::IgnoreWhitespace;
 use super::LineNumberStyle;
+use super::Markers;
+use super::OutputFormat;
+use super::RefineScope;
 use clap::App;
 use clap::AppSettings;
 use clap::Arg;
 use clap::ArgMatches;
+use std::convert::TryFrom;
+use std::ffi::OsStr;
 use std::fmt::Display;
 use std::fmt::Error as FmtErr;
 use std::fmt::Formatter;
+use std::path::PathBuf;
 use std::str::FromStr;
 use termcolor::Color;
 use termcolor::ColorSpec;
@@ -21,12 +34,40 @@ diffr reads from standard input and write to standard output.
 
     Typical usage is for interactive use of diff:
     diff -u <file1> <file2> | diffr
-    git show | diffr";
+    git show | diffr
+
+    diffr can also compare two files, or two directories, itself:
+    diffr [-U <n>] <file1> <file2>
+    diffr -r <dir1> <dir2>";
 
 const FLAG_DEBUG: &str = "--debug";
+const FLAG_FORMAT: &str = "--format";
 const FLAG_HTML: &str = "--html";
+const FLAG_HTML_STYLESHEET: &str = "--html-stylesheet";
 const FLAG_COLOR: &str = "--colors";
+const FLAG_COLOR_WHEN: &str = "--color";
+const FLAG_MARKERS: &str = "--markers";
+const FLAG_REMOVED_MARKERS: &str = "--removed-markers";
+const FLAG_ADDED_MARKERS: &str = "--added-markers";
 const FLAG_LINE_NUMBERS: &str = "--line-numbers";
+const FLAG_GRANULARITY: &str = "--granularity";
+const FLAG_REFINE_SCOPE: &str = "--refine-scope";
+const FLAG_SIMILARITY_THRESHOLD: &str = "--similarity-threshold";
+const FLAG_ALGORITHM: &str = "--algorithm";
+const FLAG_IGNORE_WHITESPACE: &str = "--ignore-whitespace";
+const FLAG_IGNORE_CASE: &str = "--ignore-case";
+const FLAG_DETECT_MOVED: &str = "--detect-moved";
+const FLAG_FILE_HEADER: &str = "--file-header";
+const FLAG_DIFF_COST_LIMIT: &str = "--diff-cost-limit";
+const FLAG_HUNK_TOKEN_LIMIT: &str = "--hunk-token-limit";
+const FLAG_UNIFIED: &str = "--unified";
+const FLAG_RECURSIVE: &str = "--recursive";
+const FLAG_SIDE_BY_SIDE: &str = "--side-by-side";
+const FLAG_TABS: &str = "--tabs";
+const FLAG_WIDTH: &str = "--width";
+const FLAG_WRAP: &str = "--wrap";
+const FLAG_WRAP_GLYPH: &str = "--wrap-glyph";
+const ARG_FILES: &str = "FILE";
 
 #[derive(Debug, Clone, Copy)]
 enum FaceName {
@@ -34,6 +75,11 @@ enum FaceName {
     RefineAdded,
     Removed,
     RefineRemoved,
+    MovedAdded,
+    MovedRemoved,
+    FileHeader,
+    FileHeaderInfo,
+    LineNumber,
 }
 
 impl EnumString for FaceName {
@@ -44,6 +90,11 @@ impl EnumString for FaceName {
             ("refine-added", RefineAdded),
             ("removed", Removed),
             ("refine-removed", RefineRemoved),
+            ("moved-added", MovedAdded),
+            ("moved-removed", MovedRemoved),
+            ("file-header", FileHeader),
+            ("file-header-info", FileHeaderInfo),
+            ("line-number", LineNumber),
         ]
     }
 }
@@ -56,6 +107,11 @@ impl Display for FaceName {
             RefineAdded => write!(f, "refine-added"),
             Removed => write!(f, "removed"),
             RefineRemoved => write!(f, "refine-removed"),
+            MovedAdded => write!(f, "moved-added"),
+            MovedRemoved => write!(f, "moved-removed"),
+            FileHeader => write!(f, "file-header"),
+            FileHeaderInfo => write!(f, "file-header-info"),
+            LineNumber => write!(f, "line-number"),
         }
     }
 }
@@ -68,6 +124,11 @@ impl FaceName {
             RefineAdded => &mut config.refine_added_face,
             Removed => &mut config.removed_face,
             RefineRemoved => &mut config.refine_removed_face,
+            MovedAdded => &mut config.moved_added_face,
+            MovedRemoved => &mut config.moved_removed_face,
+            FileHeader => &mut config.file_header_face,
+            FileHeaderInfo => &mut config.file_header_info_face,
+            LineNumber => &mut config.line_number_face,
         }
     }
 }
@@ -123,6 +184,115 @@ impl EnumString for LineNumberStyleOpt {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+struct GranularityOpt(Granularity);
+
+impl EnumString for GranularityOpt {
+    fn data() -> &'static [(&'static str, Self)] {
+        use Granularity::*;
+        &[
+            ("char", GranularityOpt(Char)),
+            ("word", GranularityOpt(Word)),
+            ("subword", GranularityOpt(Subword)),
+            ("whitespace", GranularityOpt(Whitespace)),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RefineScopeOpt(RefineScope);
+
+impl EnumString for RefineScopeOpt {
+    fn data() -> &'static [(&'static str, Self)] {
+        use RefineScope::*;
+        &[
+            ("hunk", RefineScopeOpt(Hunk)),
+            ("lines", RefineScopeOpt(Lines)),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AlgorithmOpt(Algorithm);
+
+impl EnumString for AlgorithmOpt {
+    fn data() -> &'static [(&'static str, Self)] {
+        use Algorithm::*;
+        &[
+            ("myers", AlgorithmOpt(Myers)),
+            ("patience", AlgorithmOpt(Patience)),
+            ("histogram", AlgorithmOpt(Histogram)),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct IgnoreWhitespaceOpt(IgnoreWhitespace);
+
+impl EnumString for IgnoreWhitespaceOpt {
+    fn data() -> &'static [(&'static str, Self)] {
+        use IgnoreWhitespace::*;
+        &[
+            ("all", IgnoreWhitespaceOpt(All)),
+            ("amount", IgnoreWhitespaceOpt(Amount)),
+            ("ends", IgnoreWhitespaceOpt(Ends)),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct FileHeaderStyleOpt(FileHeaderStyle);
+
+impl EnumString for FileHeaderStyleOpt {
+    fn data() -> &'static [(&'static str, Self)] {
+        use FileHeaderStyle::*;
+        &[
+            ("raw", FileHeaderStyleOpt(Raw)),
+            ("banner", FileHeaderStyleOpt(Banner)),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct HtmlModeOpt(HtmlMode);
+
+impl EnumString for HtmlModeOpt {
+    fn data() -> &'static [(&'static str, Self)] {
+        use HtmlMode::*;
+        &[
+            ("document", HtmlModeOpt(Document)),
+            ("fragment", HtmlModeOpt(Fragment)),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OutputFormatOpt(OutputFormat);
+
+impl EnumString for OutputFormatOpt {
+    fn data() -> &'static [(&'static str, Self)] {
+        use OutputFormat::*;
+        &[
+            ("text", OutputFormatOpt(Text)),
+            ("json", OutputFormatOpt(Json)),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ColorWhenOpt(ColorWhen);
+
+impl EnumString for ColorWhenOpt {
+    fn data() -> &'static [(&'static str, Self)] {
+        use ColorWhen::*;
+        &[
+            ("auto", ColorWhenOpt(Auto)),
+            ("always", ColorWhenOpt(Always)),
+            ("never", ColorWhenOpt(Never)),
+        ]
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 enum FaceColor {
     Foreground,
@@ -165,6 +335,20 @@ enum ArgParsingError {
     Color(ParseColorError),
     MissingValue(FaceName),
     LineNumberStyle(String),
+    Granularity(String),
+    RefineScope(String),
+    SimilarityThreshold(String),
+    Algorithm(String),
+    IgnoreWhitespace(String),
+    FileHeaderStyle(String),
+    Limit(String),
+    Context(String),
+    Files(String),
+    Width(String),
+    Tabs(String),
+    HtmlMode(String),
+    ColorWhen(String),
+    OutputFormat(String),
 }
 
 impl Display for ArgParsingError {
@@ -181,6 +365,28 @@ impl Display for ArgParsingError {
             ArgParsingError::LineNumberStyle(err) => {
                 write!(f, "unexpected line number style: {}", err)
             }
+            ArgParsingError::Granularity(err) => write!(f, "unexpected granularity: {}", err),
+            ArgParsingError::RefineScope(err) => write!(f, "unexpected refine scope: {}", err),
+            ArgParsingError::SimilarityThreshold(err) => {
+                write!(f, "unexpected similarity threshold: {}", err)
+            }
+            ArgParsingError::Algorithm(err) => write!(f, "unexpected algorithm: {}", err),
+            ArgParsingError::IgnoreWhitespace(err) => {
+                write!(f, "unexpected ignore whitespace mode: {}", err)
+            }
+            ArgParsingError::FileHeaderStyle(err) => {
+                write!(f, "unexpected file header style: {}", err)
+            }
+            ArgParsingError::Limit(err) => write!(f, "unexpected limit: {}", err),
+            ArgParsingError::Context(err) => {
+                write!(f, "unexpected number of context lines: {}", err)
+            }
+            ArgParsingError::Files(err) => write!(f, "unexpected files: {}", err),
+            ArgParsingError::Width(err) => write!(f, "unexpected width: {}", err),
+            ArgParsingError::Tabs(err) => write!(f, "unexpected tab width: {}", err),
+            ArgParsingError::HtmlMode(err) => write!(f, "unexpected html mode: {}", err),
+            ArgParsingError::ColorWhen(err) => write!(f, "unexpected color mode: {}", err),
+            ArgParsingError::OutputFormat(err) => write!(f, "unexpected output format: {}", err),
         }
     }
 }
@@ -206,6 +412,62 @@ impl FromStr for LineNumberStyleOpt {
     }
 }
 
+impl FromStr for GranularityOpt {
+    type Err = ArgParsingError;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        tryparse(input).map_err(ArgParsingError::Granularity)
+    }
+}
+
+impl FromStr for RefineScopeOpt {
+    type Err = ArgParsingError;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        tryparse(input).map_err(ArgParsingError::RefineScope)
+    }
+}
+
+impl FromStr for AlgorithmOpt {
+    type Err = ArgParsingError;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        tryparse(input).map_err(ArgParsingError::Algorithm)
+    }
+}
+
+impl FromStr for IgnoreWhitespaceOpt {
+    type Err = ArgParsingError;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        tryparse(input).map_err(ArgParsingError::IgnoreWhitespace)
+    }
+}
+
+impl FromStr for FileHeaderStyleOpt {
+    type Err = ArgParsingError;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        tryparse(input).map_err(ArgParsingError::FileHeaderStyle)
+    }
+}
+
+impl FromStr for HtmlModeOpt {
+    type Err = ArgParsingError;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        tryparse(input).map_err(ArgParsingError::HtmlMode)
+    }
+}
+
+impl FromStr for OutputFormatOpt {
+    type Err = ArgParsingError;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        tryparse(input).map_err(ArgParsingError::OutputFormat)
+    }
+}
+
+impl FromStr for ColorWhenOpt {
+    type Err = ArgParsingError;
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        tryparse(input).map_err(ArgParsingError::ColorWhen)
+    }
+}
+
 fn ignore<T>(_: T) {}
 
 fn parse_line_number_style<'a, Values>(
@@ -224,6 +486,172 @@ where
     Ok(())
 }
 
+fn parse_html_mode<'a, Values>(
+    config: &mut AppConfig,
+    values: Values,
+) -> Result<(), ArgParsingError>
+where
+    Values: Iterator<Item = &'a str>,
+{
+    let mode = if let Some(mode) = values.last() {
+        mode.parse::<HtmlModeOpt>()?.0
+    } else {
+        HtmlMode::Document
+    };
+    config.html = Some(mode);
+    Ok(())
+}
+
+fn parse_output_format(config: &mut AppConfig, value: &str) -> Result<(), ArgParsingError> {
+    config.format = value.parse::<OutputFormatOpt>()?.0;
+    Ok(())
+}
+
+/// Whether the output is colored: with --color=auto, only if it is a
+/// terminal. The environment can disable the colors, and so can
+/// --markers, unless --color=always is given.
+///
+/// The refined segments are marked with --markers or --color=never:
+/// when the colors are only disabled by the environment, the text of
+/// the diff is left as is.
+fn parse_color_when(
+    config: &mut AppConfig,
+    value: Option<&str>,
+    markers: bool,
+) -> Result<(), ArgParsingError> {
+    let when = match value {
+        Some(value) => Some(value.parse::<ColorWhenOpt>()?.0),
+        None => None,
+    };
+    config.color = match when {
+        Some(ColorWhen::Always) => true,
+        Some(ColorWhen::Never) => false,
+        _ if markers => false,
+        Some(ColorWhen::Auto) => terminal::color_allowed() && terminal::is_stdout_tty(),
+        None => terminal::color_allowed(),
+    };
+    let never = matches!(when, Some(ColorWhen::Never));
+    if (markers || never) && config.html.is_none() {
+        config.markers = Some(Markers::default());
+    }
+    Ok(())
+}
+
+fn parse_granularity(config: &mut AppConfig, value: &str) -> Result<(), ArgParsingError> {
+    config.granularity = value.parse::<GranularityOpt>()?.0;
+    Ok(())
+}
+
+fn parse_refine_scope(config: &mut AppConfig, value: &str) -> Result<(), ArgParsingError> {
+    config.refine_scope = value.parse::<RefineScopeOpt>()?.0;
+    Ok(())
+}
+
+fn parse_similarity_threshold(config: &mut AppConfig, value: &str) -> Result<(), ArgParsingError> {
+    match value.parse::<f64>() {
+        Ok(threshold) if (0.0..=1.0).contains(&threshold) => {
+            config.similarity_threshold = threshold;
+            Ok(())
+        }
+        _ => Err(ArgParsingError::SimilarityThreshold(format!(
+            "got '{}', expected a number between 0 and 1",
+            value
+        ))),
+    }
+}
+
+fn parse_algorithm(config: &mut AppConfig, value: &str) -> Result<(), ArgParsingError> {
+    config.algorithm = value.parse::<AlgorithmOpt>()?.0;
+    Ok(())
+}
+
+fn parse_ignore_whitespace<'a, Values>(
+    config: &mut AppConfig,
+    values: Values,
+) -> Result<(), ArgParsingError>
+where
+    Values: Iterator<Item = &'a str>,
+{
+    let mode = if let Some(mode) = values.last() {
+        mode.parse::<IgnoreWhitespaceOpt>()?.0
+    } else {
+        IgnoreWhitespace::All
+    };
+    config.ignore_whitespace = Some(mode);
+    Ok(())
+}
+
+fn parse_file_header_style(config: &mut AppConfig, value: &str) -> Result<(), ArgParsingError> {
+    config.file_header_style = value.parse::<FileHeaderStyleOpt>()?.0;
+    Ok(())
+}
+
+fn parse_limit(flag: &str, value: &str) -> Result<usize, ArgParsingError> {
+    match value.parse::<usize>() {
+        Ok(limit) if limit != 0 => Ok(limit),
+        _ => Err(ArgParsingError::Limit(format!(
+            "got '{}' for {}, expected a positive integer",
+            value, flag
+        ))),
+    }
+}
+
+fn parse_context_lines(config: &mut AppConfig, value: &str) -> Result<(), ArgParsingError> {
+    match value.parse::<usize>() {
+        Ok(context_lines) => {
+            config.context_lines = context_lines;
+            Ok(())
+        }
+        _ => Err(ArgParsingError::Context(format!(
+            "got '{}', expected a non-negative integer",
+            value
+        ))),
+    }
+}
+
+fn parse_width(config: &mut AppConfig, value: &str) -> Result<(), ArgParsingError> {
+    match value.parse::<usize>() {
+        Ok(width) if width != 0 => {
+            config.width = Some(width);
+            Ok(())
+        }
+        _ => Err(ArgParsingError::Width(format!(
+            "got '{}', expected a positive integer",
+            value
+        ))),
+    }
+}
+
+fn parse_tabs(config: &mut AppConfig, value: &str) -> Result<(), ArgParsingError> {
+    match value.parse::<usize>() {
+        Ok(tab_width) if tab_width != 0 => {
+            config.tab_width = Some(tab_width);
+            Ok(())
+        }
+        _ => Err(ArgParsingError::Tabs(format!(
+            "got '{}', expected a positive integer",
+            value
+        ))),
+    }
+}
+
+fn parse_files<'a, Values>(config: &mut AppConfig, values: Values) -> Result<(), ArgParsingError>
+where
+    Values: Iterator<Item = &'a OsStr>,
+{
+    let files = values.map(PathBuf::from).collect::<Vec<_>>();
+    match <[PathBuf; 2]>::try_from(files) {
+        Ok([old, new]) => {
+            config.files = Some((old, new));
+            Ok(())
+        }
+        Err(files) => Err(ArgParsingError::Files(format!(
+            "got {} file(s), expected two files to compare",
+            files.len()
+        ))),
+    }
+}
+
 fn parse_color_attributes<'a, Values>(
     config: &mut AppConfig,
     mut values: Values,
@@ -283,7 +711,122 @@ fn get_matches() -> ArgMatches<'static> {
         .about(ABOUT)
         .usage(USAGE)
         .arg(Arg::with_name(FLAG_DEBUG).long(FLAG_DEBUG).hidden(true))
-        .arg(Arg::with_name(FLAG_HTML).long(FLAG_HTML).hidden(true))
+        .arg(
+            Arg::with_name(FLAG_HTML)
+                .long(FLAG_HTML)
+                .value_name("document|fragment")
+                .default_value("document")
+                .min_values(0)
+                .require_equals(true)
+                .help("Write the output as HTML. Mode is optional.")
+                .long_help(
+                    "Write the output as HTML. Mode is optional.
+When mode = 'document', write a standalone HTML document (default).
+When mode = 'fragment', only write the <pre> element containing the diff, to
+be included in another document. The mode is given like --html=fragment.
+Each face is a CSS class named after it ('added', 'refine-added', ...), and the
+line numbers have the class 'line-number'. The stylesheet of the faces is
+embedded in the document, unless --html-stylesheet is given. The diff of each
+file starts with an anchor 'file-N', N counting the files from 1.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_HTML_STYLESHEET)
+                .long(FLAG_HTML_STYLESHEET)
+                .value_name("URL")
+                .takes_value(true)
+                .help("Link to a stylesheet in the HTML output.")
+                .long_help(
+                    "Link to a stylesheet in the HTML output.
+The stylesheet at URL is used instead of the one embedded in the document, and
+is linked before the <pre> element of a fragment.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_FORMAT)
+                .long(FLAG_FORMAT)
+                .value_name("text|json")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    FLAG_HTML,
+                    FLAG_SIDE_BY_SIDE,
+                    FLAG_MARKERS,
+                    FLAG_REMOVED_MARKERS,
+                    FLAG_ADDED_MARKERS,
+                    FLAG_WRAP,
+                ])
+                .help("Set the format of the output.")
+                .long_help(
+                    "Set the format of the output.
+When format = 'text', the diff is displayed with its refinements (default).
+When format = 'json', each line of the output is a JSON object, with a 'type':
+- 'file' for the header of the diff of a file, with its 'old_path' and
+  'new_path' (null for added and deleted files), its 'status' and 'binary';
+- 'hunk' for a hunk, with its 'header' line, the 'old' ranges of its header,
+  one per parent, and the 'new' one as [start, count], and its 'lines';
+- 'text' for the other lines, like the message of a commit.
+Each line of a hunk has a 'kind' (context, added, removed, no-newline or
+separator) and a 'text', without the prefix of the diff. The added and
+removed lines also have the 'refined' and 'shared' segments of their text, as
+[start, end) byte ranges of its UTF-8 encoding, where invalid UTF-8 is replaced
+with U+FFFD. This option cannot be used with --html, --side-by-side, the
+markers and --wrap, and the other options of the display are ignored.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_COLOR_WHEN)
+                .long(FLAG_COLOR_WHEN)
+                .value_name("auto|always|never")
+                .takes_value(true)
+                .help("Set when the output is colored.")
+                .long_help(
+                    "Set when the output is colored.
+When mode = 'always', the output is colored.
+When mode = 'never', the output is not colored.
+When mode = 'auto', the output is colored only if it is a terminal.
+By default, the output is colored, even when it is a pipe to a pager, unless the
+variable NO_COLOR is set to a non-empty value or TERM is 'dumb'; they also
+apply to 'auto'. Without colors, the escape codes of the input are removed
+too, and the text of the diff is left as is. With an explicit 'never', the
+refined segments are delimited by markers instead, see --markers.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_MARKERS)
+                .long(FLAG_MARKERS)
+                .help("Delimit the refined segments with markers instead of colors.")
+                .long_help(
+                    "Delimit the refined segments with markers instead of colors.
+The refined segments of the removed lines are written like [-removed-], and
+the ones of the added lines like {+added+}, as git diff --word-diff=plain
+does, so that the refinements can be read as plain text. The output is not
+colored, unless --color=always is given.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_REMOVED_MARKERS)
+                .long(FLAG_REMOVED_MARKERS)
+                .value_names(&["OPEN", "CLOSE"])
+                .number_of_values(2)
+                .help("Set the markers of the refined segments of removed lines.")
+                .long_help(
+                    "Set the markers of the refined segments of removed lines.
+The segments are written between OPEN and CLOSE (default: '[-' and '-]').
+This implies --markers.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_ADDED_MARKERS)
+                .long(FLAG_ADDED_MARKERS)
+                .value_names(&["OPEN", "CLOSE"])
+                .number_of_values(2)
+                .help("Set the markers of the refined segments of added lines.")
+                .long_help(
+                    "Set the markers of the refined segments of added lines.
+The segments are written between OPEN and CLOSE (default: '{+' and '+}').
+This implies --markers.",
+                ),
+        )
         .arg(
             Arg::with_name(FLAG_COLOR)
                 .long(FLAG_COLOR)
@@ -303,6 +846,14 @@ There are four faces to customize:
 | unique segment | refine-added | refine-removed |
 +----------------+--------------+----------------+
 
+With --detect-moved, the common segments of moved lines use the faces
+'moved-added' and 'moved-removed' instead.
+
+With --file-header=banner, the path of the files uses the face 'file-header',
+and the description of the change (rename, new file...) 'file-header-info'.
+
+With --html, the line numbers use the face 'line-number'.
+
 The customization allows
 - to change the foreground or background color;
 - to set or unset the attributes 'bold', 'intense', 'underline';
@@ -341,7 +892,8 @@ a blue background, written with a bold font.",
             Arg::with_name(FLAG_LINE_NUMBERS)
                 .long(FLAG_LINE_NUMBERS)
                 .value_name("compact|aligned")
-                .default_value("compact")
+                .min_values(0)
+                .require_equals(true)
                 .help("Display line numbers. Style is optional.")
                 .long_help(
                     "Display line numbers. Style is optional.
@@ -349,6 +901,253 @@ When style = 'compact', take as little width as possible.
 When style = 'aligned', align to tab stops (useful if tab is used for indentation).",
                 ),
         )
+        .arg(
+            Arg::with_name(FLAG_GRANULARITY)
+                .long(FLAG_GRANULARITY)
+                .value_name("char|word|subword|whitespace")
+                .takes_value(true)
+                .help("Set the unit of word-level diff.")
+                .long_help(
+                    "Set the unit of word-level diff.
+When granularity = 'char', each character is compared on its own.
+When granularity = 'word', words and punctuation characters are compared (default).
+When granularity = 'subword', words are further split at case transitions,
+underscores and letter/digit boundaries (`getUserName` is `get`, `User`, `Name`).
+When granularity = 'whitespace', only split at blank characters.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_REFINE_SCOPE)
+                .long(FLAG_REFINE_SCOPE)
+                .value_name("hunk|lines")
+                .takes_value(true)
+                .help("Set which lines are compared with each other.")
+                .long_help(
+                    "Set which lines are compared with each other.
+When scope = 'lines', removed lines are first paired with their most similar
+added lines, and each pair is compared on its own (default).
+When scope = 'hunk', all the removed and added lines of a hunk are compared
+as a whole, like previous versions of diffr did by default.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_SIMILARITY_THRESHOLD)
+                .long(FLAG_SIMILARITY_THRESHOLD)
+                .value_name("RATIO")
+                .takes_value(true)
+                .help("Do not refine lines sharing less than RATIO of their tokens.")
+                .long_help(
+                    "Do not refine lines sharing less than RATIO of their tokens.
+RATIO is a number between 0 and 1 (default: 0). When the proportion of tokens
+shared between removed and added lines is below RATIO, the lines are displayed
+with the 'added' and 'removed' faces only, since word-level diff information
+between unrelated lines is mostly noise.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_ALGORITHM)
+                .long(FLAG_ALGORITHM)
+                .value_name("myers|patience|histogram")
+                .takes_value(true)
+                .help("Set the algorithm used for word-level diff.")
+                .long_help(
+                    "Set the algorithm used for word-level diff.
+When algorithm = 'myers', compute a longest common subsequence (default).
+When algorithm = 'patience', anchor the diff on the tokens appearing once in
+both sides; this often gives more readable results when braces or keywords
+repeat.
+When algorithm = 'histogram', use the histogram algorithm of git, which
+extends 'patience' to tokens appearing a few times.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_IGNORE_WHITESPACE)
+                .long(FLAG_IGNORE_WHITESPACE)
+                .value_name("all|amount|ends")
+                .default_value("all")
+                .min_values(0)
+                .require_equals(true)
+                .help("Ignore whitespace in word-level diff. Mode is optional.")
+                .long_help(
+                    "Ignore whitespace in word-level diff. Mode is optional.
+When mode = 'all', whitespace is not compared at all (default).
+When mode = 'amount', runs of whitespace of different lengths are equal.
+When mode = 'ends', whitespace at the beginning and end of lines is not compared.
+The mode is given like --ignore-whitespace=amount. The whitespace is still
+displayed as is.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_IGNORE_CASE)
+                .long(FLAG_IGNORE_CASE)
+                .help("Ignore case differences in word-level diff.")
+                .long_help(
+                    "Ignore case differences in word-level diff.
+Tokens differing only in case are considered equal, so that a change from
+'SELECT' to 'select' is not highlighted. The lines are still displayed as is.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_DETECT_MOVED)
+                .long(FLAG_DETECT_MOVED)
+                .help("Highlight the blocks of lines moved within the diff.")
+                .long_help(
+                    "Highlight the blocks of lines moved within the diff.
+Removed lines appearing again as added lines elsewhere in the diff, in the same
+file or in another one, are displayed with the 'moved-removed' and 'moved-added'
+faces, and the edits inside the moved block are refined. Leading and trailing
+whitespace is ignored when looking for moved lines, and blocks with less than
+20 alphanumeric characters are ignored. The whole diff is read before anything
+is displayed.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_FILE_HEADER)
+                .long(FLAG_FILE_HEADER)
+                .value_name("raw|banner")
+                .takes_value(true)
+                .require_equals(true)
+                .help("Set how the headers of the diff of each file are displayed.")
+                .long_help(
+                    "Set how the headers of the diff of each file are displayed.
+When style = 'raw', the header lines are displayed as is (default).
+When style = 'banner', the header lines (diff --git, index, ---, +++, rename
+from, new file mode, ...) are replaced by a single line with the path of the
+file, an arrow for renames and copies, and a short description of the change.
+The style is given like --file-header=banner.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_DIFF_COST_LIMIT)
+                .long(FLAG_DIFF_COST_LIMIT)
+                .value_name("N")
+                .takes_value(true)
+                .help("Approximate the word-level diff when it gets too expensive.")
+                .long_help(
+                    "Approximate the word-level diff when it gets too expensive.
+When the search for a longest common subsequence takes more than N iterations,
+it gives up and splits the lines at the furthest point it reached, like the
+heuristic of GNU diff. The result may then highlight more than necessary, but
+pathological hunks (lock files, minified code) are processed quickly. By
+default, the search is not limited.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_HUNK_TOKEN_LIMIT)
+                .long(FLAG_HUNK_TOKEN_LIMIT)
+                .value_name("N")
+                .takes_value(true)
+                .help("Do not refine hunks with more than N tokens.")
+                .long_help(
+                    "Do not refine hunks with more than N tokens.
+Hunks with more than N tokens in their removed and added lines are displayed
+with the 'added' and 'removed' faces only. By default, all hunks are refined.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_UNIFIED)
+                .short("U")
+                .long(FLAG_UNIFIED)
+                .value_name("N")
+                .takes_value(true)
+                .help("Set the number of context lines when comparing two files.")
+                .long_help(
+                    "Set the number of context lines when comparing two files.
+When two files are given, N lines around each change are displayed (default: 3).",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_RECURSIVE)
+                .short("r")
+                .long(FLAG_RECURSIVE)
+                .help("Compare two directories file by file.")
+                .long_help(
+                    "Compare two directories file by file.
+The files of both directories and their subdirectories are paired by path, and
+each pair of different files is compared with a git-style header. Files only in
+the first directory are displayed as deleted, and files only in the second one
+as new.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_SIDE_BY_SIDE)
+                .long(FLAG_SIDE_BY_SIDE)
+                .help("Display the old and new versions of the hunks in two columns.")
+                .long_help(
+                    "Display the old and new versions of the hunks in two columns.
+The removed and context lines are displayed on the left with their line
+numbers in the old file, and the added and context lines on the right with
+their line numbers in the new file. Paired removed and added lines are on the
+same row, and the lines too long for their column are wrapped. The columns
+fill the width of the terminal, or the width given with --width. The combined
+diffs of merges and the hunks of context diffs are displayed as usual.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_WIDTH)
+                .long(FLAG_WIDTH)
+                .value_name("COLS")
+                .takes_value(true)
+                .help("Set the width of the output.")
+                .long_help(
+                    "Set the width of the output, in characters.
+By default, the width of the terminal is used: the variable COLUMNS if it is
+set, like git does for its pager, or else the size of the terminal.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_WRAP)
+                .long(FLAG_WRAP)
+                .value_name("COLS")
+                .takes_value(true)
+                .min_values(0)
+                .require_equals(true)
+                .help("Wrap the lines longer than the width of the output.")
+                .long_help(
+                    "Wrap the lines longer than the width of the output.
+The lines of the hunks are broken at the start of a word when possible, and
+the next rows start with an empty margin and a continuation glyph. The width
+is COLS if given, like --wrap=100, or else the width of --width or of the
+terminal. Tabs are expanded to spaces in the wrapped lines.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_WRAP_GLYPH)
+                .long(FLAG_WRAP_GLYPH)
+                .value_name("GLYPH")
+                .takes_value(true)
+                .help("Set the text starting the rows of the wrapped lines.")
+                .long_help(
+                    "Set the text starting the rows of the wrapped lines (default: '↪').
+It is displayed in place of the '+' and '-' of the diff, with the face of the
+wrapped line.",
+                ),
+        )
+        .arg(
+            Arg::with_name(FLAG_TABS)
+                .long(FLAG_TABS)
+                .value_name("N")
+                .takes_value(true)
+                .help("Expand tabs to spaces, with a tab stop every N characters.")
+                .long_help(
+                    "Expand tabs to spaces, with a tab stop every N characters.
+The tab stops of the lines of the hunks start after their '+' and '-' prefix
+and the margin of --line-numbers, so that the lines are indented like in the
+files. The padding of --line-numbers=aligned is also made of spaces, up to a
+multiple of N. By default, tabs are written as is, and expanded every 8
+characters in the lines laid out by --side-by-side and --wrap.",
+                ),
+        )
+        .arg(
+            Arg::with_name(ARG_FILES)
+                .multiple(true)
+                .help("Compare two files instead of reading a diff from stdin.")
+                .long_help(
+                    "Compare two files instead of reading a diff from stdin.
+diffr computes the line-level diff of the two files and refines it, like
+'diff -u <file1> <file2> | diffr'. '-' stands for the standard input.",
+                ),
+        )
         .get_matches()
 }
 
@@ -359,22 +1158,160 @@ fn die(err: ArgParsingError) -> ! {
 
 pub fn parse_config() -> AppConfig {
     let matches = get_matches();
-    if atty::is(atty::Stream::Stdin) {
+    if !matches.is_present(ARG_FILES) && atty::is(atty::Stream::Stdin) {
         eprintln!("{}", matches.usage());
         std::process::exit(-1)
     }
 
     let mut config = AppConfig::default();
     config.debug = matches.is_present(FLAG_DEBUG);
-    config.html = matches.is_present(FLAG_HTML);
-    if matches.occurrences_of(FLAG_LINE_NUMBERS) != 0 {
-        if let Some(values) = matches.values_of(FLAG_LINE_NUMBERS) {
-            if let Err(err) = parse_line_number_style(&mut config, values) {
+    config.html_stylesheet = matches.value_of(FLAG_HTML_STYLESHEET).map(str::to_owned);
+    config.ignore_case = matches.is_present(FLAG_IGNORE_CASE);
+    config.detect_moved = matches.is_present(FLAG_DETECT_MOVED);
+    config.recursive = matches.is_present(FLAG_RECURSIVE);
+    config.side_by_side = matches.is_present(FLAG_SIDE_BY_SIDE);
+    config.wrap = matches.is_present(FLAG_WRAP);
+    if let Some(glyph) = matches.value_of(FLAG_WRAP_GLYPH) {
+        config.wrap_glyph = glyph.to_owned();
+    }
+    if matches.occurrences_of(FLAG_HTML) != 0 {
+        if let Some(values) = matches.values_of(FLAG_HTML) {
+            if let Err(err) = parse_html_mode(&mut config, values) {
                 die(err);
             }
         }
+    }
+
+    if let Some(value) = matches.value_of(FLAG_FORMAT) {
+        if let Err(err) = parse_output_format(&mut config, value) {
+            die(err);
+        }
+    }
+
+    let use_markers = [FLAG_MARKERS, FLAG_REMOVED_MARKERS, FLAG_ADDED_MARKERS]
+        .iter()
+        .any(|flag| matches.is_present(flag));
+    if let Err(err) = parse_color_when(&mut config, matches.value_of(FLAG_COLOR_WHEN), use_markers)
+    {
+        die(err);
+    }
+    if let Some(markers) = &mut config.markers {
+        for (flag, delimiters) in &mut [
+            (FLAG_REMOVED_MARKERS, &mut markers.removed),
+            (FLAG_ADDED_MARKERS, &mut markers.added),
+        ] {
+            if let Some(mut values) = matches.values_of(*flag) {
+                if let (Some(open), Some(close)) = (values.next(), values.next()) {
+                    **delimiters = (open.to_owned(), close.to_owned());
+                }
+            }
+        }
+    }
+
+    let mut files = matches
+        .values_of_os(ARG_FILES)
+        .map(|values| values.collect::<Vec<_>>());
+    if matches.occurrences_of(FLAG_LINE_NUMBERS) != 0 {
+        let mut values = matches
+            .values_of(FLAG_LINE_NUMBERS)
+            .map_or(vec![], Iterator::collect);
+        // without `=`, the style is taken as the first file: only two
+        // files are compared
+        if values.is_empty() {
+            if let Some(paths) = &mut files {
+                if paths.len() != 2 {
+                    values.extend(paths.remove(0).to_str());
+                    if paths.is_empty() {
+                        files = None;
+                    }
+                }
+            }
+        }
+        if let Err(err) = parse_line_number_style(&mut config, values.into_iter()) {
+            die(err);
+        }
     };
 
+    if let Some(value) = matches.value_of(FLAG_GRANULARITY) {
+        if let Err(err) = parse_granularity(&mut config, value) {
+            die(err);
+        }
+    }
+
+    if let Some(value) = matches.value_of(FLAG_REFINE_SCOPE) {
+        if let Err(err) = parse_refine_scope(&mut config, value) {
+            die(err);
+        }
+    }
+
+    if let Some(value) = matches.value_of(FLAG_SIMILARITY_THRESHOLD) {
+        if let Err(err) = parse_similarity_threshold(&mut config, value) {
+            die(err);
+        }
+    }
+
+    if let Some(value) = matches.value_of(FLAG_ALGORITHM) {
+        if let Err(err) = parse_algorithm(&mut config, value) {
+            die(err);
+        }
+    }
+
+    if matches.occurrences_of(FLAG_IGNORE_WHITESPACE) != 0 {
+        if let Some(values) = matches.values_of(FLAG_IGNORE_WHITESPACE) {
+            if let Err(err) = parse_ignore_whitespace(&mut config, values) {
+                die(err);
+            }
+        }
+    }
+
+    if let Some(value) = matches.value_of(FLAG_FILE_HEADER) {
+        if let Err(err) = parse_file_header_style(&mut config, value) {
+            die(err);
+        }
+    }
+
+    for (flag, limit) in &mut [
+        (FLAG_DIFF_COST_LIMIT, &mut config.diff_cost_limit),
+        (FLAG_HUNK_TOKEN_LIMIT, &mut config.hunk_token_limit),
+    ] {
+        if let Some(value) = matches.value_of(*flag) {
+            match parse_limit(flag, value) {
+                Ok(value) => **limit = Some(value),
+                Err(err) => die(err),
+            }
+        }
+    }
+
+    if let Some(value) = matches.value_of(FLAG_UNIFIED) {
+        if let Err(err) = parse_context_lines(&mut config, value) {
+            die(err);
+        }
+    }
+
+    for flag in &[FLAG_WIDTH, FLAG_WRAP] {
+        if let Some(value) = matches.value_of(flag) {
+            if let Err(err) = parse_width(&mut config, value) {
+                die(err);
+            }
+        }
+    }
+
+    if let Some(value) = matches.value_of(FLAG_TABS) {
+        if let Err(err) = parse_tabs(&mut config, value) {
+            die(err);
+        }
+    }
+
+    if let Some(values) = files {
+        if let Err(err) = parse_files(&mut config, values.into_iter()) {
+            die(err);
+        }
+    } else if config.recursive {
+        if let Err(err) = parse_files(&mut config, std::iter::empty()) {
+            die(err);
+        }
+    }
+
     if let Some(values) = matches.values_of(FLAG_COLOR) {
         if let Err(err) = parse_color_args(&mut config, values) {
             die(err);
@@ -0,0 +1,309 @@
+//! Comparison of two files, for `diffr <file1> <file2>`: the lines of
+//! the files are compared to produce a unified diff, which is then
+//! refined like the output of `diff -u`. With `-r`, two directories are
+//! compared file by file, with git-style headers.
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::diffr_lib::{diff, DiffInput, Snake, Span, TokenMap, Tokenization};
+
+/// The content of the file at `path`, or of the standard input for `-`.
+pub fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    if path == Path::new("-") {
+        let mut data = vec![];
+        io::stdin().read_to_end(&mut data)?;
+        Ok(data)
+    } else {
+        fs::read(path)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path.display(), err)))
+    }
+}
+
+/// The bytes of `name`, as written in a diff: on unix, any sequence of
+/// bytes is kept as is.
+pub fn name_bytes(name: &OsStr) -> Cow<'_, [u8]> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Cow::Borrowed(name.as_bytes())
+    }
+    #[cfg(not(unix))]
+    match name.to_string_lossy() {
+        Cow::Borrowed(name) => Cow::Borrowed(name.as_bytes()),
+        Cow::Owned(name) => Cow::Owned(name.into_bytes()),
+    }
+}
+
+/// Whether `data` looks like the content of a binary file.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.contains(&0)
+}
+
+/// The span of each line of `data`, including its newline.
+fn split_lines(data: &[u8]) -> Vec<Span> {
+    let mut lines = vec![];
+    let mut lo = 0;
+    for (i, &b) in data.iter().enumerate() {
+        if b == b'\n' {
+            lines.push((lo, i + 1));
+            lo = i + 1;
+        }
+    }
+    if lo < data.len() {
+        lines.push((lo, data.len()));
+    }
+    lines
+}
+
+/// The removed lines `old` replaced with the added lines `new`, as
+/// ranges of line indexes.
+#[derive(Debug, Clone, Copy)]
+struct Change {
+    old: (usize, usize),
+    new: (usize, usize),
+}
+
+/// The changes between `old_lines` and `new_lines`, from the longest
+/// common subsequence of their lines.
+fn changes(old: &[u8], old_lines: &[Span], new: &[u8], new_lines: &[Span]) -> Vec<Change> {
+    // each distinct line is a token
+    let m = TokenMap::new(
+        &mut [(old_lines.iter(), old), (new_lines.iter(), new)],
+        Default::default(),
+    );
+    let removed = Tokenization::new(old, old_lines, &m);
+    let added = Tokenization::new(new, new_lines, &m);
+    let input = DiffInput::new(&added, &removed);
+    let mut v = vec![];
+    let mut snakes = vec![];
+    diff(&input, &mut v, &mut snakes);
+
+    let end = Snake {
+        x0: old_lines.len() as isize,
+        y0: new_lines.len() as isize,
+        len: 0,
+    };
+    let mut changes = vec![];
+    let (mut x, mut y) = (0, 0);
+    for snake in snakes.iter().chain(Some(&end)) {
+        let (x0, y0) = (snake.x0 as usize, snake.y0 as usize);
+        if x < x0 || y < y0 {
+            changes.push(Change {
+                old: (x, x0),
+                new: (y, y0),
+            });
+        }
+        x = x0 + snake.len as usize;
+        y = y0 + snake.len as usize;
+    }
+    changes
+}
+
+/// Write a range of a hunk header, like `-3,4`.
+fn write_range(out: &mut Vec<u8>, sign: char, (lo, hi): (usize, usize)) -> io::Result<()> {
+    match hi - lo {
+        // the line after which the lines would be
+        0 => write!(out, "{}{},0", sign, lo),
+        1 => write!(out, "{}{}", sign, lo + 1),
+        len => write!(out, "{}{},{}", sign, lo + 1, len),
+    }
+}
+
+fn write_line(out: &mut Vec<u8>, prefix: u8, line: &[u8]) {
+    out.push(prefix);
+    out.extend_from_slice(line);
+    if !line.ends_with(b"\n") {
+        out.extend_from_slice(b"\n\\ No newline at end of file\n");
+    }
+}
+
+/// The unified diff of `old` and `new`, with `context` lines of context
+/// around the changes, or nothing when they are identical. The names of
+/// the files in its header are `old_name` and `new_name`.
+pub fn unified_diff(
+    old: &[u8],
+    new: &[u8],
+    old_name: &[u8],
+    new_name: &[u8],
+    context: usize,
+) -> io::Result<Vec<u8>> {
+    let mut out = vec![];
+    if old == new {
+        return Ok(out);
+    }
+    if is_binary(old) || is_binary(new) {
+        out.extend_from_slice(b"Binary files ");
+        out.extend_from_slice(old_name);
+        out.extend_from_slice(b" and ");
+        out.extend_from_slice(new_name);
+        out.extend_from_slice(b" differ\n");
+        return Ok(out);
+    }
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let changes = changes(old, &old_lines, new, &new_lines);
+    for (prefix, name) in &[(b"--- ", old_name), (b"+++ ", new_name)] {
+        out.extend_from_slice(*prefix);
+        out.extend_from_slice(name);
+        out.push(b'\n');
+    }
+
+    let mut i = 0;
+    while i < changes.len() {
+        // the changes separated by at most 2 * context lines are
+        // displayed in the same hunk
+        let mut j = i + 1;
+        while j < changes.len() && changes[j].old.0 - changes[j - 1].old.1 <= 2 * context {
+            j += 1;
+        }
+        let (first, last) = (changes[i], changes[j - 1]);
+        let before = context.min(first.old.0);
+        let after = context.min(old_lines.len() - last.old.1);
+        let old_range = (first.old.0 - before, last.old.1 + after);
+        let new_range = (first.new.0 - before, last.new.1 + after);
+        write!(out, "@@ ")?;
+        write_range(&mut out, '-', old_range)?;
+        write!(out, " ")?;
+        write_range(&mut out, '+', new_range)?;
+        writeln!(out, " @@")?;
+
+        let mut x = old_range.0;
+        for change in &changes[i..j] {
+            for &(lo, hi) in &old_lines[x..change.old.0] {
+                write_line(&mut out, b' ', &old[lo..hi]);
+            }
+            for &(lo, hi) in &old_lines[change.old.0..change.old.1] {
+                write_line(&mut out, b'-', &old[lo..hi]);
+            }
+            for &(lo, hi) in &new_lines[change.new.0..change.new.1] {
+                write_line(&mut out, b'+', &new[lo..hi]);
+            }
+            x = change.old.1;
+        }
+        for &(lo, hi) in &old_lines[x..old_range.1] {
+            write_line(&mut out, b' ', &old[lo..hi]);
+        }
+        i = j;
+    }
+    Ok(out)
+}
+
+/// The paths of the files under `dir`, relative to it. Symbolic links
+/// are not followed: they are files, whose content is their target.
+fn list_files(dir: &Path) -> io::Result<BTreeSet<PathBuf>> {
+    fn walk(dir: &Path, prefix: &Path, files: &mut BTreeSet<PathBuf>) -> io::Result<()> {
+        let entries = fs::read_dir(dir)
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", dir.display(), err)))?;
+        for entry in entries {
+            let entry = entry?;
+            let path = prefix.join(entry.file_name());
+            if fs::symlink_metadata(entry.path())?.is_dir() {
+                walk(&entry.path(), &path, files)?;
+            } else {
+                files.insert(path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = BTreeSet::new();
+    walk(dir, Path::new(""), &mut files)?;
+    Ok(files)
+}
+
+/// The name of the relative `path` in a diff, with `/` as separator.
+fn relative_name(path: &Path) -> Vec<u8> {
+    let mut name = vec![];
+    for component in path.iter() {
+        if !name.is_empty() {
+            name.push(b'/');
+        }
+        name.extend_from_slice(&name_bytes(component));
+    }
+    name
+}
+
+/// The content of the file at `path`, or the target of the symbolic
+/// link, like git.
+fn read_entry(path: &Path) -> io::Result<Vec<u8>> {
+    if fs::symlink_metadata(path)?.file_type().is_symlink() {
+        Ok(name_bytes(fs::read_link(path)?.as_os_str()).into_owned())
+    } else {
+        read_file(path)
+    }
+}
+
+/// The mode of the file at `path`, as displayed by git.
+fn file_mode(path: &Path) -> io::Result<&'static str> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.file_type().is_symlink() {
+        return Ok("120000");
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 != 0 {
+            return Ok("100755");
+        }
+    }
+    Ok("100644")
+}
+
+/// The diff of the directories `old` and `new`: the files are paired by
+/// path, and each pair of different files gets a unified diff with a
+/// git-style header. Files only in `old` are deleted, files only in
+/// `new` are new.
+pub fn diff_directories(old: &Path, new: &Path, context: usize) -> io::Result<Vec<u8>> {
+    let old_files = list_files(old)?;
+    let new_files = list_files(new)?;
+    let mut out = vec![];
+    for path in old_files.union(&new_files) {
+        let (old_path, new_path) = (old.join(path), new.join(path));
+        let in_old = old_files.contains(path);
+        let in_new = new_files.contains(path);
+        let read = |exists, path: &Path| if exists { read_entry(path) } else { Ok(vec![]) };
+        let path = relative_name(path);
+        let name = |exists, prefix: &[u8]| {
+            if exists {
+                [prefix, &path].concat()
+            } else {
+                b"/dev/null".to_vec()
+            }
+        };
+        let diff = unified_diff(
+            &read(in_old, &old_path)?,
+            &read(in_new, &new_path)?,
+            &name(in_old, b"a/"),
+            &name(in_new, b"b/"),
+            context,
+        )?;
+
+        let mut header = vec![];
+        header.extend_from_slice(b"diff --git ");
+        header.extend_from_slice(&name(true, b"a/"));
+        header.push(b' ');
+        header.extend_from_slice(&name(true, b"b/"));
+        header.push(b'\n');
+        if !in_old {
+            writeln!(header, "new file mode {}", file_mode(&new_path)?)?;
+        } else if !in_new {
+            writeln!(header, "deleted file mode {}", file_mode(&old_path)?)?;
+        } else {
+            let (old_mode, new_mode) = (file_mode(&old_path)?, file_mode(&new_path)?);
+            if old_mode != new_mode {
+                writeln!(header, "old mode {}", old_mode)?;
+                writeln!(header, "new mode {}", new_mode)?;
+            } else if diff.is_empty() {
+                continue;
+            }
+        }
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&diff);
+    }
+    Ok(out)
+}
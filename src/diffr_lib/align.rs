@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use super::TokenId;
+
+/// The minimal similarity for two lines to be paired by `align_lines`.
+const MIN_PAIR_SIMILARITY: f64 = 0.5;
+
+/// The maximal number of pairs of lines `align_lines` compares; above
+/// that, no line is paired.
+const MAX_ALIGN_CELLS: usize = 10_000;
+
+/// Count the occurrences of each token of a line.
+fn token_counts(line: &[TokenId]) -> HashMap<TokenId, usize> {
+    let mut counts = HashMap::new();
+    for tok in line {
+        *counts.entry(*tok).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// The Dice coefficient of two lines, seen as multisets of tokens.
+fn similarity(a: &HashMap<TokenId, usize>, b: &HashMap<TokenId, usize>) -> f64 {
+    let len_a: usize = a.values().sum();
+    let len_b: usize = b.values().sum();
+    if len_a + len_b == 0 {
+        return 1.0;
+    }
+    let (small, large) = if a.len() < b.len() { (a, b) } else { (b, a) };
+    let common: usize = small
+        .iter()
+        .map(|(tok, count)| (*count).min(*large.get(tok).unwrap_or(&0)))
+        .sum();
+    2.0 * (common as f64) / ((len_a + len_b) as f64)
+}
+
+/// Pair the `removed` lines with their most similar `added` lines.
+///
+/// Each line is given as its sequence of tokens. The result is the list
+/// of pairs `(i, j)` of indexes in `removed` and `added`, increasing in
+/// both components, that maximizes the total similarity of the pairs.
+/// Lines that are not similar enough are left unpaired.
+pub fn align_lines(removed: &[&[TokenId]], added: &[&[TokenId]]) -> Vec<(usize, usize)> {
+    let n = removed.len();
+    let m = added.len();
+    if n == 0 || m == 0 || n * m > MAX_ALIGN_CELLS {
+        return vec![];
+    }
+    let removed = removed.iter().map(|l| token_counts(l)).collect::<Vec<_>>();
+    let added = added.iter().map(|l| token_counts(l)).collect::<Vec<_>>();
+
+    // score[i][j]: best total similarity pairing removed[..i] with added[..j]
+    let mut score = vec![vec![0.0f64; m + 1]; n + 1];
+    let mut pair_score = vec![vec![None; m]; n];
+    for i in 1..=n {
+        for j in 1..=m {
+            let mut best = score[i - 1][j].max(score[i][j - 1]);
+            let sim = similarity(&removed[i - 1], &added[j - 1]);
+            if MIN_PAIR_SIMILARITY <= sim {
+                pair_score[i - 1][j - 1] = Some(sim);
+                best = best.max(score[i - 1][j - 1] + sim);
+            }
+            score[i][j] = best;
+        }
+    }
+
+    let mut pairs = vec![];
+    let (mut i, mut j) = (n, m);
+    while 0 < i && 0 < j {
+        match pair_score[i - 1][j - 1] {
+            Some(sim) if score[i][j] == score[i - 1][j - 1] + sim => {
+                pairs.push((i - 1, j - 1));
+                i -= 1;
+                j -= 1;
+            }
+            _ => {
+                if score[i][j] == score[i - 1][j] {
+                    i -= 1
+                } else {
+                    j -= 1
+                }
+            }
+        }
+    }
+    pairs.reverse();
+    pairs
+}
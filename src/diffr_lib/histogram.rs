@@ -0,0 +1,106 @@
+//! The histogram diff algorithm, as implemented in git.
+//!
+//! This is an extension of the patience algorithm: instead of only
+//! considering the tokens appearing once in both sequences, the
+//! longest common region containing the least frequent tokens of the
+//! removed sequence is kept, and the algorithm recurses on both sides
+//! of this region. When the two sequences have common tokens that are
+//! all too frequent, it falls back to Myers' algorithm.
+
+use std::collections::HashMap;
+
+use super::{
+    diff_rec, diff_trimmed, merge_snakes, push_snake, to_isize, DiffInput, Snake, TokenId,
+};
+
+/// Tokens appearing more often than this in the removed sequence are
+/// not used to find common regions.
+const MAX_CHAIN_LENGTH: usize = 64;
+
+/// Compute the longest common subsequence for `input` into `dst`,
+/// using the histogram algorithm.
+pub fn diff_histogram(input: &DiffInput, v: &mut Vec<isize>, dst: &mut Vec<Snake>) {
+    dst.clear();
+    histogram_rec(input, v, dst)
+}
+
+fn histogram_rec(input: &DiffInput, v: &mut Vec<isize>, dst: &mut Vec<Snake>) {
+    diff_trimmed(input, v, dst, histogram_middle)
+}
+
+fn histogram_middle(input: &DiffInput, v: &mut Vec<isize>, dst: &mut Vec<Snake>) {
+    let n = to_isize(input.n());
+    let m = to_isize(input.m());
+    let mut occurrences: HashMap<TokenId, Vec<isize>> = HashMap::new();
+    for x in 0..n {
+        occurrences.entry(input.seq_a(x)).or_default().push(x);
+    }
+    let count = |x: isize| occurrences[&input.seq_a(x)].len();
+
+    // the best region so far, as (x, y, len), and its lowest count
+    let mut best: Option<(isize, isize, isize)> = None;
+    let mut best_count = MAX_CHAIN_LENGTH + 1;
+    let mut has_common = false;
+    let mut y = 0;
+    while y < m {
+        let mut y_next = y + 1;
+        if let Some(xs) = occurrences.get(&input.seq_b(y)) {
+            if best_count < xs.len() {
+                has_common = true;
+                y = y_next;
+                continue;
+            }
+            for &x in xs {
+                let (mut x_lo, mut y_lo) = (x, y);
+                let (mut x_hi, mut y_hi) = (x + 1, y + 1);
+                let mut region_count = xs.len();
+                while 0 < x_lo && 0 < y_lo && input.seq_a(x_lo - 1) == input.seq_b(y_lo - 1) {
+                    x_lo -= 1;
+                    y_lo -= 1;
+                    if 1 < region_count {
+                        region_count = region_count.min(count(x_lo));
+                    }
+                }
+                while x_hi < n && y_hi < m && input.seq_a(x_hi) == input.seq_b(y_hi) {
+                    if 1 < region_count {
+                        region_count = region_count.min(count(x_hi));
+                    }
+                    x_hi += 1;
+                    y_hi += 1;
+                }
+                y_next = y_next.max(y_hi);
+                let len = x_hi - x_lo;
+                let is_longer = match best {
+                    Some((_, _, best_len)) => best_len < len,
+                    None => true,
+                };
+                if is_longer || region_count < best_count {
+                    best = Some((x_lo, y_lo, len));
+                    best_count = region_count;
+                }
+            }
+        }
+        y = y_next;
+    }
+
+    match best {
+        Some((x, y, len)) => {
+            let x0 = input.removed.start_index;
+            let y0 = input.added.start_index;
+            let (x, y) = (x0 + x, y0 + y);
+            histogram_rec(&input.sub_input((x0, y0), (x, y)), v, dst);
+            push_snake(dst, Snake::default().from(x, y).len(len));
+            let end = (
+                input.removed.one_past_end_index,
+                input.added.one_past_end_index,
+            );
+            histogram_rec(&input.sub_input((x + len, y + len), end), v, dst);
+        }
+        None if has_common => {
+            let start = dst.len();
+            diff_rec(input, v, dst);
+            merge_snakes(dst, start);
+        }
+        None => (),
+    }
+}
@@ -6,29 +6,80 @@
 //!
 //! The main entrypoint is `diff`, which allows to compute the longest
 //! common subsequence between two sequences of byte slices.
+//!
+//! The patience and histogram algorithms are also available (see
+//! `diff_patience` and `diff_histogram`), and `Algorithm` allows to
+//! select the algorithm at runtime.
 
+use std::borrow::Cow;
 use std::collections::hash_map::Entry::*;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::fmt::{Error as FmtErr, Formatter};
 
+mod align;
 mod best_projection;
+mod histogram;
+mod moved;
+mod patience;
+pub use align::align_lines;
 pub use best_projection::optimize_partition;
+pub use histogram::diff_histogram;
+pub use moved::{find_moved_blocks, DiffLine};
+pub use patience::diff_patience;
+
+/// A range of bytes `[lo, hi)` in some input data.
+pub type Span = (usize, usize);
+
+/// The identifier of a token in a `TokenMap`.
+pub type TokenId = u64;
+
+/// How tokens are normalized before being compared by a `TokenMap`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokenNormalization {
+    /// Consider all the blank tokens equal, whatever their length.
+    pub whitespace_amount: bool,
+    /// Consider tokens differing only in case equal.
+    pub ignore_case: bool,
+}
+
+impl TokenNormalization {
+    fn normalize<'a>(&self, token: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.whitespace_amount && is_blank(token) {
+            Cow::Borrowed(b" ")
+        } else if self.ignore_case
+            && token
+                .iter()
+                .any(|b| b.is_ascii_uppercase() || !b.is_ascii())
+        {
+            match std::str::from_utf8(token) {
+                Ok(s) if !s.is_ascii() => Cow::Owned(s.to_lowercase().into_bytes()),
+                _ => Cow::Owned(token.to_ascii_lowercase()),
+            }
+        } else {
+            Cow::Borrowed(token)
+        }
+    }
+}
 
-type Span = (usize, usize);
-
-type TokenId = u64;
-
-pub struct TokenMap<'a>(HashMap<&'a [u8], TokenId>);
+pub struct TokenMap<'a> {
+    ids: HashMap<Cow<'a, [u8]>, TokenId>,
+    normalization: TokenNormalization,
+}
 
 impl<'a> TokenMap<'a> {
-    pub fn new(input: &mut [(impl Iterator<Item = &'a Span>, &'a [u8])]) -> Self {
+    /// Assign an identifier to each distinct token of `input`, after
+    /// normalizing it according to `normalization`.
+    pub fn new(
+        input: &mut [(impl Iterator<Item = &'a Span>, &'a [u8])],
+        normalization: TokenNormalization,
+    ) -> Self {
         let mut m = HashMap::new();
         let mut counter = 0;
         for (spans, data) in input.iter_mut() {
             for span in spans {
-                let data = &data[span.0..span.1];
+                let data = normalization.normalize(&data[span.0..span.1]);
                 match m.entry(data) {
                     Vacant(e) => {
                         e.insert(counter);
@@ -38,11 +89,17 @@ impl<'a> TokenMap<'a> {
                 }
             }
         }
-        TokenMap(m)
+        TokenMap {
+            ids: m,
+            normalization,
+        }
     }
 
     fn get(&self, slice: &'a [u8]) -> TokenId {
-        self.0.get(slice).unwrap().clone()
+        self.ids
+            .get(&*self.normalization.normalize(slice))
+            .unwrap()
+            .clone()
     }
 }
 
@@ -172,6 +229,7 @@ impl<'a> TokenizationRange<'a> {
 pub struct DiffInput<'a> {
     added: TokenizationRange<'a>,
     removed: TokenizationRange<'a>,
+    cost_limit: Option<usize>,
 }
 
 impl<'a> DiffInput<'a> {
@@ -179,9 +237,18 @@ impl<'a> DiffInput<'a> {
         DiffInput {
             added: TokenizationRange::new(added),
             removed: TokenizationRange::new(removed),
+            cost_limit: None,
         }
     }
 
+    /// Bound the cost of the diff: when the search for a middle snake
+    /// takes more than `limit` iterations, an approximate snake is used
+    /// instead, and the result may not be the longest common subsequence.
+    pub fn cost_limit(mut self, limit: usize) -> Self {
+        self.cost_limit = Some(limit);
+        self
+    }
+
     pub fn added(&self) -> &Tokenization<'a> {
         self.added.t
     }
@@ -198,14 +265,30 @@ impl<'a> DiffInput<'a> {
             DiffInput {
                 added: added1,
                 removed: removed1,
+                cost_limit: self.cost_limit,
             },
             DiffInput {
                 added: added2,
                 removed: removed2,
+                cost_limit: self.cost_limit,
             },
         )
     }
 
+    /// The input restricted to the removed tokens `x0..x1` and the
+    /// added tokens `y0..y1`.
+    fn sub_input(&self, (x0, y0): (isize, isize), (x1, y1): (isize, isize)) -> Self {
+        let (_, removed) = self.removed.split_at(self.removed.start_index, x0);
+        let (_, added) = self.added.split_at(self.added.start_index, y0);
+        let (removed, _) = removed.split_at(x1, removed.one_past_end_index);
+        let (added, _) = added.split_at(y1, added.one_past_end_index);
+        DiffInput {
+            added,
+            removed,
+            cost_limit: self.cost_limit,
+        }
+    }
+
     fn n(&self) -> usize {
         self.removed.nb_tokens()
     }
@@ -364,6 +447,10 @@ impl LineSplit {
     pub fn len(&self) -> usize {
         self.data.len()
     }
+
+    pub fn nb_lines(&self) -> usize {
+        self.line_lengths.len()
+    }
 }
 
 struct LineSplitIter<'a> {
@@ -421,6 +508,67 @@ impl Snake {
     }
 }
 
+/// Push `snake` into `dst`, merging it with the last snake when they
+/// are contiguous.
+fn push_snake(dst: &mut Vec<Snake>, snake: Snake) {
+    if snake.len == 0 {
+        return;
+    }
+    if let Some(last) = dst.last_mut() {
+        if last.x0 + last.len == snake.x0 && last.y0 + last.len == snake.y0 {
+            last.len += snake.len;
+            return;
+        }
+    }
+    dst.push(snake)
+}
+
+/// Merge the contiguous snakes of `dst` starting from `start`.
+fn merge_snakes(dst: &mut Vec<Snake>, start: usize) {
+    let snakes = dst.split_off(start);
+    for snake in snakes {
+        push_snake(dst, snake)
+    }
+}
+
+/// Compute the longest common subsequence of `input` into `dst` with
+/// `diff_middle`, after having matched the common prefix and suffix of
+/// the two sequences.
+fn diff_trimmed<F>(input: &DiffInput, v: &mut Vec<isize>, dst: &mut Vec<Snake>, diff_middle: F)
+where
+    F: Fn(&DiffInput, &mut Vec<isize>, &mut Vec<Snake>),
+{
+    let n = to_isize(input.n());
+    let m = to_isize(input.m());
+    let x0 = input.removed.start_index;
+    let y0 = input.added.start_index;
+    let mut prefix = 0;
+    while prefix < n && prefix < m && input.seq_a(prefix) == input.seq_b(prefix) {
+        prefix += 1;
+    }
+    let mut suffix = 0;
+    while suffix < n - prefix
+        && suffix < m - prefix
+        && input.seq_a(n - suffix - 1) == input.seq_b(m - suffix - 1)
+    {
+        suffix += 1;
+    }
+    push_snake(dst, Snake::default().from(x0, y0).len(prefix));
+    let middle = input.sub_input(
+        (x0 + prefix, y0 + prefix),
+        (x0 + n - suffix, y0 + m - suffix),
+    );
+    if middle.n() != 0 && middle.m() != 0 {
+        diff_middle(&middle, v, dst);
+    }
+    push_snake(
+        dst,
+        Snake::default()
+            .from(x0 + n - suffix, y0 + m - suffix)
+            .len(suffix),
+    );
+}
+
 fn diff_sequences_kernel_bidirectional(
     input: &DiffInput,
     ctx_fwd: &mut DiffTraversal,
@@ -501,6 +649,29 @@ fn diff_sequences_simple(input: &DiffInput, v: &mut Vec<isize>, forward: bool) -
         .unwrap_or(max_result)
 }
 
+/// The algorithms available to compute the longest common subsequence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// Myers' algorithm: see `diff`.
+    Myers,
+    /// The patience algorithm: see `diff_patience`.
+    Patience,
+    /// The histogram algorithm: see `diff_histogram`.
+    Histogram,
+}
+
+impl Algorithm {
+    /// Compute the longest common subsequence for `input` into `dst`
+    /// with the algorithm `self`.
+    pub fn diff(self, input: &DiffInput, v: &mut Vec<isize>, dst: &mut Vec<Snake>) {
+        match self {
+            Algorithm::Myers => diff(input, v, dst),
+            Algorithm::Patience => diff_patience(input, v, dst),
+            Algorithm::Histogram => diff_histogram(input, v, dst),
+        }
+    }
+}
+
 /// Compute the longest common subsequence for `input` into `dst`.
 pub fn diff(input: &DiffInput, v: &mut Vec<isize>, dst: &mut Vec<Snake>) {
     dst.clear();
@@ -585,15 +756,51 @@ fn diff_sequences_bidirectional_snake(input: &DiffInput, v: &mut Vec<isize>) ->
     let (v1, v2) = v.split_at_mut(iter_len);
     let ctx_fwd = &mut DiffTraversal::from_slice(input, v1, true, max);
     let ctx_bwd = &mut DiffTraversal::from_slice(input, v2, false, max);
-    let mut result = (0..max)
-        .filter_map(|d| diff_sequences_kernel_bidirectional(input, ctx_fwd, ctx_bwd, d))
-        .next()
-        .expect("snake not found");
+    let mut result = None;
+    for d in 0..max {
+        result = diff_sequences_kernel_bidirectional(input, ctx_fwd, ctx_bwd, d);
+        if result.is_some() {
+            break;
+        }
+        if let Some(limit) = input.cost_limit {
+            if 1 <= d && limit <= d {
+                result = approximate_snake(input, ctx_fwd, d);
+                if result.is_some() {
+                    break;
+                }
+            }
+        }
+    }
+    let mut result = result.expect("snake not found");
     result.0.x0 += input.removed.start_index;
     result.0.y0 += input.added.start_index;
     result
 }
 
+/// Give up the search for a middle snake after `d` iterations: split
+/// `input` at the furthest point reached by the forward search, like the
+/// `TOO_EXPENSIVE` heuristic of GNU diff.
+fn approximate_snake(
+    input: &DiffInput,
+    ctx_fwd: &DiffTraversal,
+    d: usize,
+) -> Option<(Snake, isize)> {
+    let n = to_isize(input.n());
+    let m = to_isize(input.m());
+    let d = to_isize(d);
+    let (x, y) = (-d..=d)
+        .step_by(2)
+        .map(|k| (ctx_fwd.v(k), ctx_fwd.v(k) - k))
+        .filter(|&(x, y)| 0 <= x && x <= n && 0 <= y && y <= m)
+        .max_by_key(|&(x, y)| x + y)?;
+    if (x, y) == (0, 0) || (x, y) == (n, m) {
+        return None;
+    }
+    // the length of the edit script is unknown, but the caller must
+    // split the input at the snake
+    Some((Snake::default().from(x, y).len(0), 2 * d + 1))
+}
+
 fn to_isize(input: usize) -> isize {
     isize::try_from(input).unwrap()
 }
@@ -609,30 +816,201 @@ enum TokenKind {
     Spaces,
 }
 
+/// A strategy to split the input data into the tokens that the diff
+/// algorithms compare.
+pub trait Tokenizer {
+    /// Tokenize data from `src` from the position `ofs` into `tokens`.
+    ///
+    /// The spans pushed into `tokens` must be non empty, sorted and
+    /// cover `src[ofs..]`.
+    fn tokenize(&self, src: &[u8], ofs: usize, tokens: &mut Vec<Span>);
+}
+
+/// The default tokenizer: see `tokenize`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WordTokenizer;
+
+impl Tokenizer for WordTokenizer {
+    fn tokenize(&self, src: &[u8], ofs: usize, tokens: &mut Vec<Span>) {
+        tokenize(src, ofs, tokens)
+    }
+}
+
+/// A tokenizer that makes each character a token by itself.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CharTokenizer;
+
+impl Tokenizer for CharTokenizer {
+    fn tokenize(&self, src: &[u8], ofs: usize, tokens: &mut Vec<Span>) {
+        tokenize_with(src, ofs, tokens, |_| TokenKind::Other)
+    }
+}
+
+/// A tokenizer that only splits at blank characters: tokens are the
+/// runs of blank and non blank characters, and end of lines.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WhitespaceTokenizer;
+
+impl Tokenizer for WhitespaceTokenizer {
+    fn tokenize(&self, src: &[u8], ofs: usize, tokens: &mut Vec<Span>) {
+        tokenize_with(src, ofs, tokens, |c| match c {
+            '\t' | ' ' => TokenKind::Spaces,
+            '\n' | '\r' => TokenKind::Other,
+            _ => TokenKind::Word,
+        })
+    }
+}
+
+/// A tokenizer that splits words into sub-words: like `tokenize`, but
+/// identifiers are further broken at case transitions, underscores and
+/// letter/digit boundaries, so that `getUserName` becomes `get`,
+/// `User` and `Name`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SubwordTokenizer;
+
+impl Tokenizer for SubwordTokenizer {
+    fn tokenize(&self, src: &[u8], ofs: usize, tokens: &mut Vec<Span>) {
+        let start = tokens.len();
+        tokenize(src, ofs, tokens);
+        let words = tokens.split_off(start);
+        for (lo, hi) in words {
+            split_subwords(src, lo, hi, tokens)
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum SubwordKind {
+    Upper,
+    Lower,
+    Digit,
+    Underscore,
+    Other,
+}
+
+fn classify_subword_char(c: char) -> SubwordKind {
+    match c {
+        '_' => SubwordKind::Underscore,
+        c if c.is_uppercase() => SubwordKind::Upper,
+        c if c.is_numeric() => SubwordKind::Digit,
+        c if c.is_alphabetic() => SubwordKind::Lower,
+        _ => SubwordKind::Other,
+    }
+}
+
+/// Split the token `src[lo..hi]` into sub-words, pushing them into
+/// `tokens`.
+fn split_subwords(src: &[u8], lo: usize, hi: usize, tokens: &mut Vec<Span>) {
+    use SubwordKind::*;
+    let mut chars = vec![];
+    let mut i = lo;
+    while i < hi {
+        match decode_utf8(&src[i..hi]) {
+            Some((c, len)) => {
+                chars.push((i, classify_subword_char(c)));
+                i += len
+            }
+            None => {
+                chars.push((i, Other));
+                i += 1
+            }
+        }
+    }
+    if chars.iter().any(|&(_, kind)| kind == Other) {
+        tokens.push((lo, hi));
+        return;
+    }
+    let mut start = lo;
+    for j in 1..chars.len() {
+        let (pos, kind) = chars[j];
+        let prev = chars[j - 1].1;
+        let next = chars.get(j + 1).map(|&(_, kind)| kind);
+        let boundary = match (prev, kind) {
+            (Underscore, Underscore) => false,
+            (Underscore, _) | (_, Underscore) => true,
+            (Digit, Digit) => false,
+            (Digit, _) | (_, Digit) => true,
+            (Lower, Upper) => true,
+            // end of an acronym, as in `HTTPServer`
+            (Upper, Upper) => next == Some(Lower),
+            _ => false,
+        };
+        if boundary {
+            tokens.push((start, pos));
+            start = pos
+        }
+    }
+    tokens.push((start, hi));
+}
+
 /// Tokenize data from `src` from the position `ofs` into `tokens`.
+///
+/// Words, made of letters, digits and underscores, and runs of blank
+/// characters are grouped as single tokens; any other character is
+/// a token by itself.
+///
+/// The input is decoded as UTF-8: a token boundary is never emitted
+/// inside a code point. Bytes that are not valid UTF-8 are handled as
+/// single byte tokens.
 pub fn tokenize(src: &[u8], ofs: usize, tokens: &mut Vec<Span>) {
+    tokenize_with(src, ofs, tokens, classify_char)
+}
+
+/// Group consecutive characters of the same kind according to
+/// `classify`; characters of kind `TokenKind::Other` are never grouped.
+fn tokenize_with<F>(src: &[u8], ofs: usize, tokens: &mut Vec<Span>, classify: F)
+where
+    F: Fn(char) -> TokenKind,
+{
     let mut push = |lo: usize, hi: usize| {
         if lo < hi {
             tokens.push((lo, hi))
         }
     };
     let mut lo = ofs;
+    let mut hi = ofs;
     let mut kind = TokenKind::Other;
-    for hi in ofs..src.len() {
+    while hi < src.len() {
         let oldkind = kind;
-        kind = classify_byte(src[hi]);
+        let (newkind, len) = match decode_utf8(&src[hi..]) {
+            Some((c, len)) => (classify(c), len),
+            None => (TokenKind::Other, 1),
+        };
+        kind = newkind;
         if kind != oldkind || oldkind == TokenKind::Other {
             push(lo, hi);
             lo = hi
         }
+        hi += len;
     }
     push(lo, src.len());
 }
 
-fn classify_byte(b: u8) -> TokenKind {
-    match b {
-        b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' => TokenKind::Word,
-        b'\t' | b' ' => TokenKind::Spaces,
+/// Decode the first character of `buf`, returning it along with its
+/// length in bytes, or `None` if `buf` does not start with a valid
+/// UTF-8 sequence.
+fn decode_utf8(buf: &[u8]) -> Option<(char, usize)> {
+    let len = match *buf.first()? {
+        0x00..=0x7f => 1,
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => return None,
+    };
+    let c = std::str::from_utf8(buf.get(..len)?).ok()?.chars().next()?;
+    Some((c, len))
+}
+
+/// Whether `token` only contains spaces and tabs.
+pub fn is_blank(token: &[u8]) -> bool {
+    !token.is_empty() && token.iter().all(|&b| b == b' ' || b == b'\t')
+}
+
+fn classify_char(c: char) -> TokenKind {
+    match c {
+        '\t' | ' ' => TokenKind::Spaces,
+        '_' => TokenKind::Word,
+        c if c.is_alphanumeric() => TokenKind::Word,
         _ => TokenKind::Other,
     }
 }
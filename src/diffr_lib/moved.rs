@@ -0,0 +1,146 @@
+//! Detection of blocks of lines moved from one place of a diff to
+//! another, like `git diff --color-moved`.
+
+use std::collections::HashMap;
+
+use super::{is_blank, Span, Tokenizer, WordTokenizer};
+
+/// The minimal number of alphanumeric characters in the identical lines
+/// of a moved block: shorter blocks (braces, blank lines, ...) often
+/// match by chance.
+const MIN_MOVED_ALNUM: usize = 20;
+
+/// The minimal similarity for two lines that are not identical to
+/// extend a moved block.
+const MIN_EDITED_SIMILARITY: f64 = 0.5;
+
+/// A changed line of a diff.
+#[derive(Debug, Clone, Copy)]
+pub struct DiffLine<'a> {
+    /// The index of the line in the input.
+    pub index: usize,
+    /// The index of the group of consecutive changed lines containing
+    /// the line.
+    pub block: usize,
+    /// The content of the line, without its prefix.
+    pub content: &'a [u8],
+}
+
+/// A block of `len` removed lines starting at index `removed`, found
+/// again as the added lines starting at index `added`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovedBlock {
+    pub removed: usize,
+    pub added: usize,
+    pub len: usize,
+}
+
+fn trim(line: &[u8]) -> &[u8] {
+    let start = line
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(line.len());
+    let end = line
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(start, |i| i + 1);
+    &line[start..end]
+}
+
+fn count_alnum(line: &[u8]) -> usize {
+    line.iter().filter(|b| b.is_ascii_alphanumeric()).count()
+}
+
+/// The Dice coefficient of the words of two lines.
+fn similarity(a: &[u8], b: &[u8]) -> f64 {
+    let words = |line: &[u8]| {
+        let mut spans: Vec<Span> = vec![];
+        WordTokenizer.tokenize(line, 0, &mut spans);
+        let mut counts = HashMap::new();
+        for (lo, hi) in spans {
+            if !is_blank(&line[lo..hi]) {
+                *counts.entry(line[lo..hi].to_vec()).or_insert(0) += 1;
+            }
+        }
+        counts
+    };
+    let (a, b) = (words(a), words(b));
+    let len_a: usize = a.values().sum();
+    let len_b: usize = b.values().sum();
+    if len_a + len_b == 0 {
+        return 0.0;
+    }
+    let common: usize = a
+        .iter()
+        .map(|(word, count)| (*count).min(*b.get(word).unwrap_or(&0)))
+        .sum();
+    2.0 * (common as f64) / ((len_a + len_b) as f64)
+}
+
+/// Find the blocks of `removed` lines appearing again among the `added`
+/// lines, in another group of changed lines.
+///
+/// Lines are compared ignoring leading and trailing whitespace, and a
+/// block only contains lines that are consecutive in the input. A block
+/// starts with an identical line, and may then go on with lines that
+/// were edited, as long as they are similar enough.
+pub fn find_moved_blocks(removed: &[DiffLine], added: &[DiffLine]) -> Vec<MovedBlock> {
+    let mut candidates: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for (j, line) in added.iter().enumerate() {
+        let key = trim(line.content);
+        if !key.is_empty() {
+            candidates.entry(key).or_default().push(j);
+        }
+    }
+
+    let mut claimed = vec![false; added.len()];
+    let mut blocks = vec![];
+    let mut i = 0;
+    while i < removed.len() {
+        let key = trim(removed[i].content);
+        let mut best: Option<MovedBlock> = None;
+        for &j in candidates.get(key).into_iter().flatten() {
+            if claimed[j] || added[j].block == removed[i].block {
+                continue;
+            }
+            let mut len = 1;
+            let mut alnum = count_alnum(key);
+            while i + len < removed.len()
+                && j + len < added.len()
+                && !claimed[j + len]
+                && removed[i + len].index == removed[i + len - 1].index + 1
+                && added[j + len].index == added[j + len - 1].index + 1
+            {
+                let (a, b) = (removed[i + len].content, added[j + len].content);
+                if trim(a) == trim(b) {
+                    alnum += count_alnum(trim(a));
+                } else if similarity(a, b) < MIN_EDITED_SIMILARITY {
+                    break;
+                }
+                len += 1;
+            }
+            let is_better = match best {
+                Some(block) => block.len < len,
+                None => true,
+            };
+            if MIN_MOVED_ALNUM <= alnum && is_better {
+                best = Some(MovedBlock {
+                    removed: i,
+                    added: j,
+                    len,
+                });
+            }
+        }
+        match best {
+            Some(block) => {
+                for claimed in &mut claimed[block.added..block.added + block.len] {
+                    *claimed = true;
+                }
+                blocks.push(block);
+                i += block.len;
+            }
+            None => i += 1,
+        }
+    }
+    blocks
+}
@@ -0,0 +1,97 @@
+//! The patience diff algorithm, as described by Bram Cohen.
+//!
+//! Tokens appearing exactly once in both sequences are used as
+//! anchors: the longest increasing sequence of such tokens is kept,
+//! and the algorithm recurses between consecutive anchors. When there
+//! is no anchor, it falls back to Myers' algorithm.
+
+use std::collections::HashMap;
+
+use super::{
+    diff_rec, diff_trimmed, merge_snakes, push_snake, to_isize, DiffInput, Snake, TokenId,
+};
+
+/// Compute the longest common subsequence for `input` into `dst`,
+/// using the patience algorithm.
+pub fn diff_patience(input: &DiffInput, v: &mut Vec<isize>, dst: &mut Vec<Snake>) {
+    dst.clear();
+    patience_rec(input, v, dst)
+}
+
+fn patience_rec(input: &DiffInput, v: &mut Vec<isize>, dst: &mut Vec<Snake>) {
+    diff_trimmed(input, v, dst, patience_middle)
+}
+
+fn patience_middle(input: &DiffInput, v: &mut Vec<isize>, dst: &mut Vec<Snake>) {
+    let anchors = unique_anchors(input);
+    if anchors.is_empty() {
+        let start = dst.len();
+        diff_rec(input, v, dst);
+        merge_snakes(dst, start);
+    } else {
+        let (mut x, mut y) = (input.removed.start_index, input.added.start_index);
+        for (ax, ay) in anchors {
+            patience_rec(&input.sub_input((x, y), (ax, ay)), v, dst);
+            push_snake(dst, Snake::default().from(ax, ay).len(1));
+            x = ax + 1;
+            y = ay + 1;
+        }
+        let end = (
+            input.removed.one_past_end_index,
+            input.added.one_past_end_index,
+        );
+        patience_rec(&input.sub_input((x, y), end), v, dst);
+    }
+}
+
+/// Find the longest increasing sequence of tokens appearing exactly
+/// once in both sequences of `input`, as absolute positions.
+fn unique_anchors(input: &DiffInput) -> Vec<(isize, isize)> {
+    // token -> (count in removed, position in removed, count in added, position in added)
+    let mut occurrences: HashMap<TokenId, (usize, isize, usize, isize)> = HashMap::new();
+    for x in 0..to_isize(input.n()) {
+        let e = occurrences.entry(input.seq_a(x)).or_insert((0, 0, 0, 0));
+        e.0 += 1;
+        e.1 = x;
+    }
+    for y in 0..to_isize(input.m()) {
+        if let Some(e) = occurrences.get_mut(&input.seq_b(y)) {
+            e.2 += 1;
+            e.3 = y;
+        }
+    }
+    let mut candidates = occurrences
+        .values()
+        .filter(|e| e.0 == 1 && e.2 == 1)
+        .map(|e| (e.1, e.3))
+        .collect::<Vec<_>>();
+    candidates.sort();
+
+    // patience sorting: piles[i] is the index in candidates of the top
+    // of the i-th pile, and prev links each card to the top of the
+    // previous pile when it was placed.
+    let mut piles: Vec<usize> = vec![];
+    let mut prev = vec![None; candidates.len()];
+    for (i, &(_, y)) in candidates.iter().enumerate() {
+        let pile = match piles.binary_search_by(|&top| candidates[top].1.cmp(&y)) {
+            Ok(pile) | Err(pile) => pile,
+        };
+        if pile != 0 {
+            prev[i] = Some(piles[pile - 1]);
+        }
+        if pile == piles.len() {
+            piles.push(i);
+        } else {
+            piles[pile] = i;
+        }
+    }
+    let mut anchors = vec![];
+    let mut card = piles.last().cloned();
+    while let Some(i) = card {
+        let (x, y) = candidates[i];
+        anchors.push((x + input.removed.start_index, y + input.added.start_index));
+        card = prev[i];
+    }
+    anchors.reverse();
+    anchors
+}
@@ -34,7 +34,7 @@ where
 
 fn nth_token<'a>(input: &'a TokenizationRange, idx: isize) -> &'a [u8] {
     let span = input.nth_span(idx);
-    &input.t.data()[span.0..span.1]
+    &input.t.data[span.0..span.1]
 }
 
 fn compress_path(values: &Vec<(Vec<u8>, DiffKind)>) -> Vec<(Vec<u8>, DiffKind)> {
@@ -72,7 +72,7 @@ fn dummy_tokenize<'a>(data: &'a [u8]) -> Vec<Span> {
 
 fn really_tokenize<'a>(data: &'a [u8]) -> Vec<Span> {
     let mut toks = vec![];
-    tokenize(data, 0, &mut toks);
+    WordTokenizer.tokenize(data, 0, &mut toks);
     toks
 }
 
@@ -92,7 +92,10 @@ fn diff_sequences_test_aux(
 ) {
     let toks_a = tok(&seq_a);
     let toks_b = tok(&seq_b);
-    let m = TokenMap::new(&mut [(toks_a.iter(), &seq_a), (toks_b.iter(), &seq_b)]);
+    let m = TokenMap::new(
+        &mut [(toks_a.iter(), &seq_a), (toks_b.iter(), &seq_b)],
+        TokenNormalization::default(),
+    );
     let tok_a = Tokenization::new(seq_a, &toks_a, &m);
     let tok_b = Tokenization::new(seq_b, &toks_b, &m);
     let input = DiffInput::new(&tok_b, &tok_a);
@@ -373,6 +376,93 @@ fn tokenize_test() {
         &["*", "(", "abcd", ")", " ", "#", "[", "efgh", "]"],
         b"*(abcd) #[efgh]",
     );
+    test(
+        &["caf\u{e9}", " ", "na\u{ef}ve"],
+        "caf\u{e9} na\u{ef}ve".as_bytes(),
+    );
+    test(
+        &["\u{65e5}\u{672c}\u{8a9e}", "\u{3002}"],
+        "\u{65e5}\u{672c}\u{8a9e}\u{3002}".as_bytes(),
+    );
+    test(
+        &["x", " ", "=", " ", "\u{1f600}", "\u{1f600}"],
+        "x = \u{1f600}\u{1f600}".as_bytes(),
+    );
+    test(&["\u{fffd}", "\u{fffd}", "ab"], b"\xff\xc3ab");
+    test(&["ab", "\u{fffd}", "\u{fffd}"], b"ab\xe2\x82");
+}
+
+#[test]
+fn tokenizer_trait_test() {
+    struct ByteTokenizer;
+    impl Tokenizer for ByteTokenizer {
+        fn tokenize(&self, src: &[u8], ofs: usize, tokens: &mut Vec<Span>) {
+            tokens.extend((ofs..src.len()).map(|i| (i, i + 1)))
+        }
+    }
+    fn test(expected: &[&str], tokenizer: &dyn Tokenizer, buf: &[u8], ofs: usize) {
+        let mut tokens = vec![];
+        tokenizer.tokenize(buf, ofs, &mut tokens);
+        assert_eq!(expected, &to_strings(buf, tokens.into_iter())[..]);
+    }
+    test(&["cd", " ", "ef"], &WordTokenizer, b"ab cd ef", 3);
+    test(&["c", "d", " ", "e", "f"], &ByteTokenizer, b"ab cd ef", 3);
+}
+
+#[test]
+fn granularity_tokenizers_test() {
+    fn test(expected: &[&str], tokenizer: &dyn Tokenizer, buf: &[u8]) {
+        let mut tokens = vec![];
+        tokenizer.tokenize(buf, 0, &mut tokens);
+        assert_eq!(expected, &to_strings(buf, tokens.into_iter())[..]);
+    }
+    test(&[], &CharTokenizer, b"");
+    test(
+        &["f", "o", "o", "(", "4", "2", ")", "\n"],
+        &CharTokenizer,
+        b"foo(42)\n",
+    );
+    test(
+        &["\u{e9}", "t", "\u{e9}"],
+        &CharTokenizer,
+        "\u{e9}t\u{e9}".as_bytes(),
+    );
+    test(&["\u{fffd}", "a"], &CharTokenizer, b"\xffa");
+    test(&[], &WhitespaceTokenizer, b"");
+    test(
+        &["foo(bar,", " ", "baz);", "\n"],
+        &WhitespaceTokenizer,
+        b"foo(bar, baz);\n",
+    );
+    test(
+        &["a", "\t ", "b", "\r", "\n"],
+        &WhitespaceTokenizer,
+        b"a\t b\r\n",
+    );
+}
+
+#[test]
+fn subword_tokenizer_test() {
+    fn test(expected: &[&str], buf: &[u8]) {
+        let mut tokens = vec![];
+        SubwordTokenizer.tokenize(buf, 0, &mut tokens);
+        assert_eq!(expected, &to_strings(buf, tokens.into_iter())[..]);
+    }
+    test(&[], b"");
+    test(&["get", "User", "Name"], b"getUserName");
+    test(&["Get", "User", "Id", "(", ")"], b"GetUserId()");
+    test(&["get", "_", "user", "__", "name"], b"get_user__name");
+    test(&["_", "private"], b"_private");
+    test(
+        &["HTTP", "Server", " ", "utf", "8", "to", "Utf", "16"],
+        b"HTTPServer utf8toUtf16",
+    );
+    test(&["MAX", "_", "LEN", "\n"], b"MAX_LEN\n");
+    test(
+        &["\u{c9}t\u{e9}", "\u{c9}t\u{e9}"],
+        "\u{c9}t\u{e9}\u{c9}t\u{e9}".as_bytes(),
+    );
+    test(&["\u{65e5}\u{672c}"], "\u{65e5}\u{672c}".as_bytes());
 }
 
 #[test]
@@ -380,7 +470,10 @@ fn find_splitting_point_test() {
     fn test(expected: isize, seq_a: &[u8], seq_b: &[u8]) {
         let toks_a = dummy_tokenize(&seq_a);
         let toks_b = dummy_tokenize(&seq_b);
-        let m = TokenMap::new(&mut [(toks_a.iter(), &seq_a), (toks_b.iter(), &seq_b)]);
+        let m = TokenMap::new(
+            &mut [(toks_a.iter(), &seq_a), (toks_b.iter(), &seq_b)],
+            TokenNormalization::default(),
+        );
         let tok_a = Tokenization::new(seq_a, &toks_a, &m);
         let tok_b = Tokenization::new(seq_b, &toks_b, &m);
         let input = DiffInput::new(&tok_b, &tok_a);
@@ -470,7 +563,10 @@ fn test_lcs_random() {
     fn test_lcs(seq_a: &[u8], seq_b: &[u8]) {
         let toks_a = dummy_tokenize(&seq_a);
         let toks_b = dummy_tokenize(&seq_b);
-        let m = TokenMap::new(&mut [(toks_a.iter(), &seq_a), (toks_b.iter(), &seq_b)]);
+        let m = TokenMap::new(
+            &mut [(toks_a.iter(), &seq_a), (toks_b.iter(), &seq_b)],
+            TokenNormalization::default(),
+        );
         let tok_a = Tokenization::new(seq_a, &toks_a, &m);
         let tok_b = Tokenization::new(seq_b, &toks_b, &m);
         let input = DiffInput::new(&tok_a, &tok_b);
@@ -645,7 +741,10 @@ fn test_optimize_alternatives(
 ) {
     let toks_seq = dummy_tokenize(&seq);
     let toks_lcs = dummy_tokenize(&lcs);
-    let m = TokenMap::new(&mut [(toks_seq.iter(), &seq), (toks_lcs.iter(), &lcs)]);
+    let m = TokenMap::new(
+        &mut [(toks_seq.iter(), &seq), (toks_lcs.iter(), &lcs)],
+        TokenNormalization::default(),
+    );
     let seq = Tokenization::new(&seq, &toks_seq, &m);
     let lcs = Tokenization::new(&lcs, &toks_lcs, &m);
     let opt_result = optimize_partition(&seq, &lcs);
@@ -744,3 +843,355 @@ fn test_optimize_partition() {
         b"note: \r\n",
     );
 }
+
+#[test]
+fn align_lines_test() {
+    fn test(expected: &[(usize, usize)], removed: &[&[u8]], added: &[&[u8]]) {
+        let toks = |lines: &[&[u8]]| mk_vec(lines.iter().map(|l| really_tokenize(l)));
+        let toks_removed = toks(removed);
+        let toks_added = toks(added);
+        let mut input = vec![];
+        for (line, toks) in removed.iter().zip(&toks_removed) {
+            input.push((toks.iter(), *line));
+        }
+        for (line, toks) in added.iter().zip(&toks_added) {
+            input.push((toks.iter(), *line));
+        }
+        let m = TokenMap::new(&mut input, TokenNormalization::default());
+        let ids = |lines: &[&[u8]], toks: &[Vec<Span>]| {
+            mk_vec(
+                lines
+                    .iter()
+                    .zip(toks)
+                    .map(|(line, toks)| Tokenization::new(line, toks, &m).tokens().to_vec()),
+            )
+        };
+        let ids_removed = ids(removed, &toks_removed);
+        let ids_added = ids(added, &toks_added);
+        let slices_removed = mk_vec(ids_removed.iter().map(|v| &v[..]));
+        let slices_added = mk_vec(ids_added.iter().map(|v| &v[..]));
+        assert_eq!(expected, &align_lines(&slices_removed, &slices_added)[..]);
+    }
+    test(&[], &[], &[b"foo"]);
+    test(&[], &[b"foo"], &[]);
+    test(&[(0, 0)], &[b"let x = 1;"], &[b"let x = 2;"]);
+    test(&[], &[b"let x = 1;"], &[b"return"]);
+    test(
+        &[(0, 1), (1, 2)],
+        &[b"foo(a, b);", b"bar(c, d);"],
+        &[b"/* new */", b"foo(a, b, c);", b"bar(c, d, e);"],
+    );
+    // pairs are ordered
+    test(
+        &[(0, 1)],
+        &[b"a b c d", b"e f g h"],
+        &[b"e f g h i", b"a b c d e"],
+    );
+}
+
+fn line_tokenize(data: &[u8]) -> Vec<Span> {
+    let mut toks = vec![];
+    let mut lo = 0;
+    for (i, b) in data.iter().enumerate() {
+        if *b == b'\n' {
+            toks.push((lo, i + 1));
+            lo = i + 1;
+        }
+    }
+    if lo < data.len() {
+        toks.push((lo, data.len()));
+    }
+    toks
+}
+
+/// Check that `snakes` define a common subsequence of the two sides
+/// of `input`, and return it.
+fn check_snakes(input: &DiffInput, snakes: &[Snake]) -> Vec<u8> {
+    let mut lcs = vec![];
+    let (mut x, mut y) = (0, 0);
+    for &Snake { x0, y0, len } in snakes {
+        assert!(x <= x0 && y <= y0 && 0 < len, "{:?}", snakes);
+        for i in 0..len {
+            let removed = nth_token(&input.removed, x0 + i);
+            assert_eq!(removed, nth_token(&input.added, y0 + i));
+            lcs.extend_from_slice(removed);
+        }
+        x = x0 + len;
+        y = y0 + len;
+    }
+    assert!(x <= to_isize(input.n()) && y <= to_isize(input.m()));
+    lcs
+}
+
+fn diff_with_algorithm(
+    algorithm: Algorithm,
+    tok: impl Fn(&[u8]) -> Vec<Span>,
+    seq_a: &[u8],
+    seq_b: &[u8],
+) -> String {
+    let toks_a = tok(seq_a);
+    let toks_b = tok(seq_b);
+    let m = TokenMap::new(
+        &mut [(toks_a.iter(), seq_a), (toks_b.iter(), seq_b)],
+        TokenNormalization::default(),
+    );
+    let tok_a = Tokenization::new(seq_a, &toks_a, &m);
+    let tok_b = Tokenization::new(seq_b, &toks_b, &m);
+    let input = DiffInput::new(&tok_b, &tok_a);
+    let mut v = vec![];
+    let mut dst = vec![];
+    algorithm.diff(&input, &mut v, &mut dst);
+    string_of_bytes(&check_snakes(&input, &dst))
+}
+
+#[test]
+fn patience_test() {
+    let test = |expected: &str, seq_a: &[u8], seq_b: &[u8]| {
+        assert_eq!(
+            expected,
+            diff_with_algorithm(Algorithm::Patience, dummy_tokenize, seq_a, seq_b)
+        );
+    };
+    test("", b"", b"");
+    test("", b"abc", b"");
+    test("abc", b"abc", b"abc");
+    test("ac", b"abc", b"adc");
+    test("bc", b"abc", b"bcd");
+    // only one of the unique tokens can be kept
+    test("c", b"abc", b"cba");
+
+    let seq_a = b"void f() {\n    x += 1;\n}\n\nvoid g() {\n    x += 2;\n}\n";
+    let seq_b =
+        b"void f() {\n    x += 1;\n}\n\nvoid h() {\n    x += 3;\n}\n\nvoid g() {\n    x += 2;\n}\n";
+    assert_eq!(
+        string_of_bytes(seq_a),
+        diff_with_algorithm(Algorithm::Patience, line_tokenize, seq_a, seq_b)
+    );
+}
+
+#[test]
+fn patience_random_test() {
+    let len_a = 6;
+    let len_b = 6;
+    let nletters = 3_u8;
+    let mut seq_a = vec![b'1'; len_a];
+    let mut seq_b = vec![b'1'; len_b];
+    for i in 0..len_a {
+        for j in 0..len_b {
+            for la in 0..nletters {
+                for lb in 0..nletters {
+                    seq_a[i] = la;
+                    seq_b[j] = lb;
+                    diff_with_algorithm(Algorithm::Patience, dummy_tokenize, &seq_a, &seq_b);
+                    diff_with_algorithm(Algorithm::Patience, dummy_tokenize, &seq_b, &seq_a);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn histogram_test() {
+    let test = |expected: &str, seq_a: &[u8], seq_b: &[u8]| {
+        assert_eq!(
+            expected,
+            diff_with_algorithm(Algorithm::Histogram, dummy_tokenize, seq_a, seq_b)
+        );
+    };
+    test("", b"", b"");
+    test("", b"abc", b"");
+    test("abc", b"abc", b"abc");
+    test("ac", b"abc", b"adc");
+    test("bc", b"abc", b"bcd");
+    // no unique token: the least frequent tokens are used
+    test("aab", b"aabbx", b"yaab");
+    test("", b"abc", b"def");
+
+    let seq_a = b"void f() {\n    x += 1;\n}\n\nvoid g() {\n    x += 2;\n}\n";
+    let seq_b =
+        b"void f() {\n    x += 1;\n}\n\nvoid h() {\n    x += 3;\n}\n\nvoid g() {\n    x += 2;\n}\n";
+    assert_eq!(
+        string_of_bytes(seq_a),
+        diff_with_algorithm(Algorithm::Histogram, line_tokenize, seq_a, seq_b)
+    );
+}
+
+#[test]
+fn histogram_random_test() {
+    let len_a = 6;
+    let len_b = 6;
+    let nletters = 3_u8;
+    let mut seq_a = vec![b'1'; len_a];
+    let mut seq_b = vec![b'1'; len_b];
+    for i in 0..len_a {
+        for j in 0..len_b {
+            for la in 0..nletters {
+                for lb in 0..nletters {
+                    seq_a[i] = la;
+                    seq_b[j] = lb;
+                    diff_with_algorithm(Algorithm::Histogram, dummy_tokenize, &seq_a, &seq_b);
+                    diff_with_algorithm(Algorithm::Histogram, dummy_tokenize, &seq_b, &seq_a);
+                }
+            }
+        }
+    }
+}
+
+fn diff_with_cost_limit(limit: usize, seq_a: &[u8], seq_b: &[u8]) -> String {
+    let toks_a = dummy_tokenize(seq_a);
+    let toks_b = dummy_tokenize(seq_b);
+    let m = TokenMap::new(
+        &mut [(toks_a.iter(), seq_a), (toks_b.iter(), seq_b)],
+        TokenNormalization::default(),
+    );
+    let tok_a = Tokenization::new(seq_a, &toks_a, &m);
+    let tok_b = Tokenization::new(seq_b, &toks_b, &m);
+    let input = DiffInput::new(&tok_b, &tok_a).cost_limit(limit);
+    let mut v = vec![];
+    let mut dst = vec![];
+    diff(&input, &mut v, &mut dst);
+    string_of_bytes(&check_snakes(&input, &dst))
+}
+
+#[test]
+fn cost_limit_test() {
+    // cheap diffs are not affected by the limit
+    assert_eq!("abc", diff_with_cost_limit(1, b"abc", b"abc"));
+    assert_eq!("ac", diff_with_cost_limit(1, b"abc", b"adc"));
+    assert_eq!("", diff_with_cost_limit(1, b"abc", b""));
+
+    // expensive ones give a common subsequence, not always the longest
+    let is_subsequence = |sub: &str, seq: &[u8]| {
+        let mut seq = seq.iter();
+        sub.bytes().all(|b| seq.any(|&c| c == b))
+    };
+    let seq_a = b"abcdefghijklmnopqrstuvwxyz0123456789".repeat(4);
+    let seq_b = b"zyxwvutsrqponmlkjihgfedcba9876543210".repeat(4);
+    let exact = diff_with_cost_limit(usize::MAX, &seq_a, &seq_b);
+    for limit in 1..10 {
+        // the snakes are checked by `check_snakes`: increasing, within
+        // the input, and matching equal tokens
+        let approx = diff_with_cost_limit(limit, &seq_a, &seq_b);
+        assert!(!approx.is_empty() && approx.len() <= exact.len());
+        assert!(is_subsequence(&approx, &seq_a) && is_subsequence(&approx, &seq_b));
+    }
+    assert_eq!(
+        exact,
+        diff_with_algorithm(Algorithm::Myers, dummy_tokenize, &seq_a, &seq_b)
+    );
+}
+
+#[test]
+fn cost_limit_random_test() {
+    let len_a = 6;
+    let len_b = 6;
+    let nletters = 3_u8;
+    let mut seq_a = vec![b'1'; len_a];
+    let mut seq_b = vec![b'2'; len_b];
+    for i in 0..len_a {
+        for j in 0..len_b {
+            for la in 0..nletters {
+                for lb in 0..nletters {
+                    seq_a[i] = la;
+                    seq_b[j] = lb;
+                    diff_with_cost_limit(1, &seq_a, &seq_b);
+                    diff_with_cost_limit(2, &seq_b, &seq_a);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn token_normalization_test() {
+    let test = |expected: isize, normalization: TokenNormalization, seq_a: &[u8], seq_b: &[u8]| {
+        let toks_a = really_tokenize(seq_a);
+        let toks_b = really_tokenize(seq_b);
+        let m = TokenMap::new(
+            &mut [(toks_a.iter(), seq_a), (toks_b.iter(), seq_b)],
+            normalization,
+        );
+        let tok_a = Tokenization::new(seq_a, &toks_a, &m);
+        let tok_b = Tokenization::new(seq_b, &toks_b, &m);
+        let input = DiffInput::new(&tok_b, &tok_a);
+        let mut v = vec![];
+        let mut dst = vec![];
+        diff(&input, &mut v, &mut dst);
+        assert_eq!(expected, dst.iter().map(|s| s.len).sum::<isize>());
+    };
+    let exact = TokenNormalization::default();
+    let whitespace_amount = TokenNormalization {
+        whitespace_amount: true,
+        ignore_case: false,
+    };
+    let ignore_case = TokenNormalization {
+        whitespace_amount: false,
+        ignore_case: true,
+    };
+    test(2, exact, b"a  b", b"a\tb");
+    test(3, whitespace_amount, b"a  b", b"a\tb");
+    test(2, whitespace_amount, b"a  b", b"a,b");
+    test(0, exact, b"SELECT", b"select");
+    test(1, ignore_case, b"SELECT", b"select");
+    test(
+        3,
+        ignore_case,
+        "Ärger 1_X".as_bytes(),
+        "ärger 1_x".as_bytes(),
+    );
+    test(2, ignore_case, b"a\xff", b"A\xff");
+}
+
+#[test]
+fn find_moved_blocks_test() {
+    use super::moved::MovedBlock;
+    // (index in the input, group of changed lines, content)
+    let lines = |lines: &[(usize, usize, &'static str)]| {
+        lines
+            .iter()
+            .map(|&(index, block, content)| DiffLine {
+                index,
+                block,
+                content: content.as_bytes(),
+            })
+            .collect::<Vec<_>>()
+    };
+    let removed = lines(&[
+        (1, 0, "fn helper_function(x: usize) -> usize {\n"),
+        (2, 0, "    let y = compute(x);\n"),
+        (3, 0, "    y + 1\n"),
+        (4, 0, "}\n"),
+        (5, 0, "unrelated line\n"),
+        (9, 1, "}\n"),
+    ]);
+    let added = lines(&[
+        (7, 1, "    fn helper_function(x: usize) -> usize {\n"),
+        (8, 1, "        let y = compute(x, 2);\n"),
+        (10, 2, "        y + 1\n"),
+        (11, 2, "}\n"),
+    ]);
+    assert_eq!(
+        vec![MovedBlock {
+            removed: 0,
+            added: 0,
+            len: 2,
+        }],
+        find_moved_blocks(&removed, &added)
+    );
+
+    // short blocks are ignored
+    let removed = lines(&[(1, 0, "}\n"), (2, 0, "x = 1;\n")]);
+    let added = lines(&[(5, 1, "}\n"), (6, 1, "x = 1;\n")]);
+    assert_eq!(
+        Vec::<MovedBlock>::new(),
+        find_moved_blocks(&removed, &added)
+    );
+
+    // lines changed in place are not moved
+    let removed = lines(&[(1, 0, "let some_long_variable_name = 1;\n")]);
+    let added = lines(&[(2, 0, "    let some_long_variable_name = 1;\n")]);
+    assert_eq!(
+        Vec::<MovedBlock>::new(),
+        find_moved_blocks(&removed, &added)
+    );
+}
@@ -0,0 +1,303 @@
+//! Parsing of the headers introducing the diff of each file, including
+//! the extended headers of git (renames, modes, binary files...).
+
+use std::io;
+
+use super::{
+    json, output, parse_context_range, strip_escape_codes, AppConfig, Output, OutputFormat,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileStatus {
+    #[default]
+    Modified,
+    Added,
+    Deleted,
+    Renamed,
+    Copied,
+}
+
+/// What the header of the diff of a file says about it.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FileHeader {
+    /// The path of the file before the change, if it existed.
+    pub old_path: Option<Vec<u8>>,
+    /// The path of the file after the change, if it still exists.
+    pub new_path: Option<Vec<u8>>,
+    pub status: FileStatus,
+    pub old_mode: Option<Vec<u8>>,
+    pub new_mode: Option<Vec<u8>>,
+    /// The similarity index of a renamed or copied file, like `95%`.
+    pub similarity: Option<Vec<u8>>,
+    pub binary: bool,
+}
+
+impl FileHeader {
+    /// The path of the file, with an arrow for renames and copies.
+    fn path(&self) -> Vec<u8> {
+        match (&self.old_path, &self.new_path) {
+            (Some(old), Some(new)) if old != new => {
+                let mut path = old.clone();
+                path.extend_from_slice(" → ".as_bytes());
+                path.extend_from_slice(new);
+                path
+            }
+            (_, Some(path)) | (Some(path), None) => path.clone(),
+            (None, None) => vec![],
+        }
+    }
+
+    /// A short description of the change, like `new file, mode 100755`.
+    fn info(&self) -> Vec<u8> {
+        let mut info: Vec<Vec<u8>> = vec![];
+        match self.status {
+            FileStatus::Modified => (),
+            FileStatus::Added => info.push(b"new file".to_vec()),
+            FileStatus::Deleted => info.push(b"deleted".to_vec()),
+            FileStatus::Renamed | FileStatus::Copied => {
+                let mut status = if self.status == FileStatus::Renamed {
+                    b"renamed".to_vec()
+                } else {
+                    b"copied".to_vec()
+                };
+                if let Some(similarity) = &self.similarity {
+                    status.extend_from_slice(b", ");
+                    status.extend_from_slice(similarity);
+                    status.extend_from_slice(b" similar");
+                }
+                info.push(status);
+            }
+        }
+        match (&self.old_mode, &self.new_mode) {
+            (Some(old), Some(new)) => {
+                let mut mode = b"mode ".to_vec();
+                mode.extend_from_slice(old);
+                mode.extend_from_slice(" → ".as_bytes());
+                mode.extend_from_slice(new);
+                info.push(mode);
+            }
+            (None, Some(mode)) | (Some(mode), None) if self.status != FileStatus::Modified => {
+                let mut mode_info = b"mode ".to_vec();
+                mode_info.extend_from_slice(mode);
+                info.push(mode_info);
+            }
+            _ => (),
+        }
+        if self.binary {
+            info.push(b"binary".to_vec());
+        }
+        info.join(&b", "[..])
+    }
+}
+
+/// The path of a `---` or `+++` line, without the `a/` or `b/` prefix
+/// of git and the timestamp of diff.
+fn parse_path(path: &[u8], prefix: &[u8]) -> Option<Vec<u8>> {
+    let path = match path.iter().position(|&b| b == b'\t') {
+        Some(tab) => &path[..tab],
+        None => path,
+    };
+    if path == b"/dev/null" {
+        return None;
+    }
+    Some(path.strip_prefix(prefix).unwrap_or(path).to_vec())
+}
+
+/// The paths of a `diff --git a/x b/y` line. They may contain spaces,
+/// so this is only reliable when both are the same.
+fn parse_git_paths(paths: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let paths = paths.strip_prefix(b"a/")?;
+    let len = paths.len().checked_sub(3)? / 2;
+    let (old, new) = paths.split_at(len);
+    let sep = if new.starts_with(b" b/") && old == &new[3..] {
+        len
+    } else {
+        paths.windows(3).position(|w| w == b" b/")?
+    };
+    Some((paths[..sep].to_vec(), paths[sep + 3..].to_vec()))
+}
+
+fn trim_end(line: &[u8]) -> &[u8] {
+    let end = line
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+    &line[..end]
+}
+
+/// Collect the lines of the header of the diff of a file, to display
+/// them as a single banner.
+#[derive(Default)]
+pub struct FileHeaderParser {
+    raw_lines: Vec<Vec<u8>>,
+    header: Option<FileHeader>,
+    /// Whether the paths have the `a/` and `b/` prefixes of git.
+    git: bool,
+    /// Whether the header is the one of a context diff, where the old
+    /// path follows `***` and the new one `---`.
+    context: bool,
+}
+
+impl FileHeaderParser {
+    /// Try to add `line`, a line outside of the hunks, to the current
+    /// header. Returns false if it is not part of a header: the pending
+    /// header should then be flushed and the line displayed as is.
+    pub fn push(&mut self, line: &[u8]) -> bool {
+        let sanitized = strip_escape_codes(line);
+        let sanitized = trim_end(&sanitized);
+        if let Some(paths) = sanitized.strip_prefix(b"diff --git ") {
+            if self.header.is_some() {
+                return false;
+            }
+            let mut header = FileHeader::default();
+            if let Some((old, new)) = parse_git_paths(paths) {
+                header.old_path = Some(old);
+                header.new_path = Some(new);
+            }
+            self.header = Some(header);
+            self.git = true;
+            self.context = false;
+        } else if let Some(header) = &mut self.header {
+            if !Self::parse_line(header, sanitized, self.git, self.context) {
+                return false;
+            }
+        } else if let Some(path) = sanitized.strip_prefix(b"--- ") {
+            // plain unified diff, without the line of git
+            let header = FileHeader {
+                old_path: parse_path(path, b""),
+                ..Default::default()
+            };
+            self.header = Some(header);
+            self.git = false;
+            self.context = false;
+        } else if let Some(path) = sanitized
+            .strip_prefix(b"*** ")
+            .filter(|_| parse_context_range(sanitized, b"***", b"****").is_none())
+        {
+            // context diff, the new path follows on the `---` line
+            let old_path = parse_path(path, b"");
+            let header = FileHeader {
+                status: if old_path.is_none() {
+                    FileStatus::Added
+                } else {
+                    FileStatus::Modified
+                },
+                old_path,
+                ..Default::default()
+            };
+            self.header = Some(header);
+            self.git = false;
+            self.context = true;
+        } else {
+            return false;
+        }
+        self.raw_lines.push(line.to_vec());
+        true
+    }
+
+    fn parse_line(header: &mut FileHeader, line: &[u8], git: bool, context: bool) -> bool {
+        let field = |prefix: &[u8]| line.strip_prefix(prefix).map(|s| s.to_vec());
+        let (old_prefix, new_prefix): (&[u8], &[u8]) =
+            if git { (b"a/", b"b/") } else { (b"", b"") };
+        if let Some(path) = line.strip_prefix(b"--- ").filter(|_| context) {
+            header.new_path = parse_path(path, new_prefix);
+            if header.new_path.is_none() {
+                header.status = FileStatus::Deleted;
+            }
+        } else if let Some(path) = line.strip_prefix(b"--- ") {
+            header.old_path = parse_path(path, old_prefix);
+            if header.old_path.is_none() {
+                header.status = FileStatus::Added;
+            }
+        } else if let Some(path) = line.strip_prefix(b"+++ ") {
+            header.new_path = parse_path(path, new_prefix);
+            if header.new_path.is_none() {
+                header.status = FileStatus::Deleted;
+            }
+        } else if let Some(path) = field(b"rename from ") {
+            header.status = FileStatus::Renamed;
+            header.old_path = Some(path);
+        } else if let Some(path) = field(b"rename to ") {
+            header.status = FileStatus::Renamed;
+            header.new_path = Some(path);
+        } else if let Some(path) = field(b"copy from ") {
+            header.status = FileStatus::Copied;
+            header.old_path = Some(path);
+        } else if let Some(path) = field(b"copy to ") {
+            header.status = FileStatus::Copied;
+            header.new_path = Some(path);
+        } else if let Some(mode) = field(b"new file mode ") {
+            header.status = FileStatus::Added;
+            header.old_path = None;
+            header.new_mode = Some(mode);
+        } else if let Some(mode) = field(b"deleted file mode ") {
+            header.status = FileStatus::Deleted;
+            header.new_path = None;
+            header.old_mode = Some(mode);
+        } else if let Some(mode) = field(b"old mode ") {
+            header.old_mode = Some(mode);
+        } else if let Some(mode) = field(b"new mode ") {
+            header.new_mode = Some(mode);
+        } else if let Some(similarity) = field(b"similarity index ") {
+            header.similarity = Some(similarity);
+        } else if line.starts_with(b"dissimilarity index ") || line.starts_with(b"index ") {
+        } else if line.starts_with(b"Binary files ") && line.ends_with(b" differ")
+            || line == b"GIT binary patch"
+        {
+            header.binary = true;
+        } else {
+            return false;
+        }
+        true
+    }
+
+    /// Display the pending header, if any, and start over.
+    pub fn flush<Stream>(&mut self, config: &AppConfig, out: &mut Stream) -> io::Result<()>
+    where
+        Stream: Output,
+    {
+        let json = config.format == OutputFormat::Json;
+        match self.header.take() {
+            Some(header) if json && (header.old_path.is_some() || header.new_path.is_some()) => {
+                json::write_file(out, &header)?;
+            }
+            _ if json => {
+                for line in &self.raw_lines {
+                    json::write_text(out, line)?;
+                }
+            }
+            Some(header) if header.old_path.is_some() || header.new_path.is_some() => {
+                out.start_file()?;
+                let path = header.path();
+                output(&path, 0, path.len(), &config.file_header_face, out)?;
+                let info = header.info();
+                if !info.is_empty() {
+                    out.write_all(b" ")?;
+                    let mut info_line = b"(".to_vec();
+                    info_line.extend_from_slice(&info);
+                    info_line.push(b')');
+                    output(
+                        &info_line,
+                        0,
+                        info_line.len(),
+                        &config.file_header_info_face,
+                        out,
+                    )?;
+                }
+                out.write_all(b"\n")?;
+            }
+            _ => {
+                for line in &self.raw_lines {
+                    output(line, 0, line.len(), &Default::default(), out)?;
+                }
+            }
+        }
+        self.raw_lines.clear();
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn header(&self) -> Option<&FileHeader> {
+        self.header.as_ref()
+    }
+}
@@ -0,0 +1,261 @@
+//! HTML output, for `--html`: the diff is a `<pre>` element where each
+//! face is a CSS class, in a standalone document or alone.
+
+use std::io::{self, Write};
+use termcolor::{Color, ColorSpec, WriteColor};
+
+use super::{AppConfig, HtmlMode, Output};
+
+/// The CSS declarations of the text of the diff, before the rules of
+/// the faces.
+const BASE_STYLE: &str = "\
+pre.diffr { font-family: monospace; }
+pre.diffr .line-number { user-select: none; }
+";
+
+/// The RGB value of the colors of the terminal, normal and intense,
+/// like xterm.
+fn named_color_rgb(color: &Color, intense: bool) -> Option<(u8, u8, u8)> {
+    use Color::*;
+    let index = match color {
+        Black => 0,
+        Red => 1,
+        Green => 2,
+        Yellow => 3,
+        Blue => 4,
+        Magenta => 5,
+        Cyan => 6,
+        White => 7,
+        _ => return None,
+    };
+    Some(ansi256_rgb(if intense { index + 8 } else { index }))
+}
+
+/// The RGB value of a color of the 256 colors palette of xterm.
+fn ansi256_rgb(index: u8) -> (u8, u8, u8) {
+    const SYSTEM: [(u8, u8, u8); 16] = [
+        (0x00, 0x00, 0x00),
+        (0xcd, 0x00, 0x00),
+        (0x00, 0xcd, 0x00),
+        (0xcd, 0xcd, 0x00),
+        (0x00, 0x00, 0xee),
+        (0xcd, 0x00, 0xcd),
+        (0x00, 0xcd, 0xcd),
+        (0xe5, 0xe5, 0xe5),
+        (0x7f, 0x7f, 0x7f),
+        (0xff, 0x00, 0x00),
+        (0x00, 0xff, 0x00),
+        (0xff, 0xff, 0x00),
+        (0x5c, 0x5c, 0xff),
+        (0xff, 0x00, 0xff),
+        (0x00, 0xff, 0xff),
+        (0xff, 0xff, 0xff),
+    ];
+    match index {
+        0..=15 => SYSTEM[index as usize],
+        16..=231 => {
+            // a 6x6x6 cube
+            let level = |x: u8| if x == 0 { 0 } else { 55 + 40 * x };
+            let index = index - 16;
+            (level(index / 36), level(index / 6 % 6), level(index % 6))
+        }
+        _ => {
+            let gray = 8 + 10 * (index - 232);
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// The CSS value of `color`, or None for the colors termcolor may add.
+fn css_color(color: &Color, intense: bool) -> Option<String> {
+    let (r, g, b) = match color {
+        Color::Ansi256(index) => ansi256_rgb(*index),
+        Color::Rgb(r, g, b) => (*r, *g, *b),
+        color => named_color_rgb(color, intense)?,
+    };
+    Some(format!("#{:02x}{:02x}{:02x}", r, g, b))
+}
+
+/// The CSS declarations rendering `spec`.
+fn css_declarations(spec: &ColorSpec) -> String {
+    let mut declarations = String::new();
+    if let Some(fg) = spec.fg().and_then(|fg| css_color(fg, spec.intense())) {
+        declarations.push_str(&format!("color: {}; ", fg));
+    }
+    if let Some(bg) = spec.bg().and_then(|bg| css_color(bg, spec.intense())) {
+        declarations.push_str(&format!("background-color: {}; ", bg));
+    }
+    if spec.bold() {
+        declarations.push_str("font-weight: bold; ");
+    }
+    if spec.italic() {
+        declarations.push_str("font-style: italic; ");
+    }
+    if spec.underline() {
+        declarations.push_str("text-decoration: underline; ");
+    }
+    declarations.trim_end().to_owned()
+}
+
+/// The stylesheet of the faces of `config`, one class per face.
+pub fn stylesheet(config: &AppConfig) -> String {
+    let mut stylesheet = BASE_STYLE.to_owned();
+    for (name, face) in config.faces().iter() {
+        stylesheet.push_str(&format!(
+            "pre.diffr .{} {{ {} }}\n",
+            name,
+            css_declarations(face)
+        ));
+    }
+    stylesheet
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '&' => escaped.push_str("&amp;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Write the text of the diff as HTML: the text is escaped, and each
+/// segment with a face is wrapped in a `<span>` with the class of the
+/// face, or with its style if it is not one of the faces of the
+/// configuration. Segments without text get no `<span>`.
+pub struct HtmlColorWriter<W: Write> {
+    inner: W,
+    mode: HtmlMode,
+    /// The faces of the configuration, with their class.
+    classes: Vec<(&'static str, ColorSpec)>,
+    /// The start tag of the `<span>` of the current face, until text
+    /// is written.
+    pending_span: Option<String>,
+    in_span: bool,
+    nb_files: usize,
+}
+
+impl<W: Write> HtmlColorWriter<W> {
+    /// Write the start of the document to `inner`.
+    pub fn new(mut inner: W, config: &AppConfig, mode: HtmlMode) -> io::Result<Self> {
+        let link = config.html_stylesheet.as_ref().map(|url| {
+            format!(
+                "<link rel=\"stylesheet\" type=\"text/css\" href=\"{}\">\n",
+                escape(url)
+            )
+        });
+        match mode {
+            HtmlMode::Document => {
+                write!(
+                    inner,
+                    "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>diffr</title>\n"
+                )?;
+                match &link {
+                    Some(link) => write!(inner, "{}", link)?,
+                    None => write!(inner, "<style>\n{}</style>\n", stylesheet(config))?,
+                }
+                write!(inner, "</head>\n<body>\n")?;
+            }
+            HtmlMode::Fragment => {
+                if let Some(link) = &link {
+                    write!(inner, "{}", link)?;
+                }
+            }
+        }
+        write!(inner, "<pre class=\"diffr\">")?;
+        Ok(HtmlColorWriter {
+            inner,
+            mode,
+            classes: config
+                .faces()
+                .iter()
+                .map(|&(name, face)| (name, face.clone()))
+                .collect(),
+            pending_span: None,
+            in_span: false,
+            nb_files: 0,
+        })
+    }
+}
+
+impl<W: Write> WriteColor for HtmlColorWriter<W> {
+    fn supports_color(&self) -> bool {
+        true
+    }
+
+    fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
+        self.reset()?;
+        if spec.is_none() {
+            return Ok(());
+        }
+        self.pending_span = Some(match self.classes.iter().find(|(_, face)| face == spec) {
+            Some((name, _)) => format!("<span class=\"{}\">", name),
+            None => format!("<span style=\"{}\">", escape(&css_declarations(spec))),
+        });
+        Ok(())
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        self.pending_span = None;
+        if self.in_span {
+            self.inner.write_all(b"</span>")?;
+            self.in_span = false;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for HtmlColorWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if let Some(span) = self.pending_span.take() {
+            self.inner.write_all(span.as_bytes())?;
+            self.in_span = true;
+        }
+        let mut lo = 0;
+        for (i, b) in buf.iter().enumerate() {
+            let escaped: &[u8] = match b {
+                b'<' => b"&lt;",
+                b'>' => b"&gt;",
+                b'"' => b"&quot;",
+                b'\'' => b"&apos;",
+                b'&' => b"&amp;",
+                _ => continue,
+            };
+            self.inner.write_all(&buf[lo..i])?;
+            self.inner.write_all(escaped)?;
+            lo = i + 1;
+        }
+        self.inner.write_all(&buf[lo..])?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Output for HtmlColorWriter<W> {
+    fn start_file(&mut self) -> io::Result<()> {
+        self.reset()?;
+        self.nb_files += 1;
+        write!(self.inner, "<a id=\"file-{}\"></a>", self.nb_files)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.reset()?;
+        self.inner.write_all(b"</pre>\n")?;
+        if self.mode == HtmlMode::Document {
+            self.inner.write_all(b"</body>\n</html>\n")?;
+        }
+        self.inner.flush()
+    }
+}
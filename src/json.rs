@@ -0,0 +1,191 @@
+//! JSON output, for `--format json`: one JSON object per line for the
+//! header of each file, each hunk and each other line of the diff, so
+//! that other tools can read the refinements without parsing escape
+//! codes.
+
+use std::io::{self, Write};
+
+use super::diffr_lib::Span;
+use super::file_header::{FileHeader, FileStatus};
+use super::HunkHeader;
+
+/// A line of a hunk: its kind, its text without the prefix of the diff
+/// and the newline, and for the changed lines, the segments of the text
+/// refined or shared with the other side, as byte ranges of the text.
+/// They are written as byte ranges of the UTF-8 text of the output,
+/// where invalid UTF-8 is replaced.
+pub struct Line<'a> {
+    pub kind: &'static str,
+    pub text: &'a [u8],
+    pub segments: Option<(Vec<Span>, Vec<Span>)>,
+    /// Whether the line was moved, with --detect-moved.
+    pub moved: bool,
+}
+
+/// The refined and the shared segments of `text`, from the sorted
+/// `shared` ones. The indentation of the line is in neither, like when
+/// it is painted.
+pub fn segments(text: &[u8], shared: impl Iterator<Item = Span>) -> (Vec<Span>, Vec<Span>) {
+    let start = text
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(text.len());
+    let (mut refined, mut kept) = (vec![], vec![]);
+    let mut y = start;
+    for (lo, hi) in shared {
+        let (lo, hi) = (lo.max(y), hi.min(text.len()));
+        if hi <= lo {
+            continue;
+        }
+        if y < lo {
+            refined.push((y, lo));
+        }
+        kept.push((lo, hi));
+        y = hi;
+    }
+    if y < text.len() {
+        refined.push((y, text.len()));
+    }
+    (refined, kept)
+}
+
+/// `bytes` decoded like `String::from_utf8_lossy`, each invalid
+/// sequence replaced with U+FFFD, and the offset in the decoded text of
+/// each offset of `bytes`, up to `bytes.len()` included.
+fn decode(bytes: &[u8]) -> (String, Vec<usize>) {
+    let mut text = String::with_capacity(bytes.len());
+    let mut offsets = Vec::with_capacity(bytes.len() + 1);
+    for chunk in bytes.utf8_chunks() {
+        let (valid, invalid) = (chunk.valid(), chunk.invalid());
+        offsets.extend((0..valid.len()).map(|i| text.len() + i));
+        text.push_str(valid);
+        if !invalid.is_empty() {
+            offsets.extend(invalid.iter().map(|_| text.len()));
+            text.push(char::REPLACEMENT_CHARACTER);
+        }
+    }
+    offsets.push(text.len());
+    (text, offsets)
+}
+
+fn write_string(out: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    write_str(out, &String::from_utf8_lossy(bytes))
+}
+
+fn write_str(out: &mut impl Write, text: &str) -> io::Result<()> {
+    out.write_all(b"\"")?;
+    for c in text.chars() {
+        match c {
+            '"' => out.write_all(b"\\\"")?,
+            '\\' => out.write_all(b"\\\\")?,
+            '\n' => out.write_all(b"\\n")?,
+            '\r' => out.write_all(b"\\r")?,
+            '\t' => out.write_all(b"\\t")?,
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32)?,
+            c => write!(out, "{}", c)?,
+        }
+    }
+    out.write_all(b"\"")
+}
+
+fn write_optional_string(out: &mut impl Write, bytes: Option<&[u8]>) -> io::Result<()> {
+    match bytes {
+        Some(bytes) => write_string(out, bytes),
+        None => write!(out, "null"),
+    }
+}
+
+fn write_spans(out: &mut impl Write, spans: &[Span]) -> io::Result<()> {
+    write_spans_with(out, spans, |i| i)
+}
+
+/// Write `spans`, with their offsets mapped by `offset`.
+fn write_spans_with(
+    out: &mut impl Write,
+    spans: &[Span],
+    offset: impl Fn(usize) -> usize,
+) -> io::Result<()> {
+    out.write_all(b"[")?;
+    for (i, &(lo, hi)) in spans.iter().enumerate() {
+        if i != 0 {
+            out.write_all(b",")?;
+        }
+        write!(out, "[{},{}]", offset(lo), offset(hi))?;
+    }
+    out.write_all(b"]")
+}
+
+fn trim_newline(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\n").unwrap_or(line)
+}
+
+/// Write the header of the diff of a file.
+pub fn write_file(out: &mut impl Write, header: &FileHeader) -> io::Result<()> {
+    let status = match header.status {
+        FileStatus::Modified => "modified",
+        FileStatus::Added => "added",
+        FileStatus::Deleted => "deleted",
+        FileStatus::Renamed => "renamed",
+        FileStatus::Copied => "copied",
+    };
+    write!(out, "{{\"type\":\"file\",\"old_path\":")?;
+    write_optional_string(out, header.old_path.as_deref())?;
+    write!(out, ",\"new_path\":")?;
+    write_optional_string(out, header.new_path.as_deref())?;
+    writeln!(
+        out,
+        ",\"status\":\"{}\",\"binary\":{}}}",
+        status, header.binary
+    )
+}
+
+/// Write a line outside of the hunks and of the headers of the files,
+/// like the message of a commit.
+pub fn write_text(out: &mut impl Write, line: &[u8]) -> io::Result<()> {
+    write!(out, "{{\"type\":\"text\",\"text\":")?;
+    write_string(out, trim_newline(line))?;
+    writeln!(out, "}}")
+}
+
+/// Write a hunk: the line starting it, the ranges of its header as
+/// `[start, count]` if they are known, one per parent for the old
+/// files, and its lines.
+pub fn write_hunk(
+    out: &mut impl Write,
+    header_line: &[u8],
+    header: Option<&HunkHeader>,
+    lines: &[Line],
+) -> io::Result<()> {
+    write!(out, "{{\"type\":\"hunk\",\"header\":")?;
+    write_string(out, trim_newline(header_line))?;
+    match header {
+        Some(header) => {
+            write!(out, ",\"old\":")?;
+            write_spans(out, &header.minus_ranges)?;
+            let (start, count) = header.plus_range;
+            write!(out, ",\"new\":[{},{}]", start, count)?;
+        }
+        None => write!(out, ",\"old\":null,\"new\":null")?,
+    }
+    write!(out, ",\"lines\":[")?;
+    for (i, line) in lines.iter().enumerate() {
+        if i != 0 {
+            out.write_all(b",")?;
+        }
+        write!(out, "{{\"kind\":\"{}\",\"text\":", line.kind)?;
+        let (text, offsets) = decode(line.text);
+        write_str(out, &text)?;
+        if let Some((refined, shared)) = &line.segments {
+            let offset = |i: usize| offsets[i.min(line.text.len())];
+            write!(out, ",\"refined\":")?;
+            write_spans_with(out, refined, offset)?;
+            write!(out, ",\"shared\":")?;
+            write_spans_with(out, shared, offset)?;
+        }
+        if line.moved {
+            write!(out, ",\"moved\":true")?;
+        }
+        out.write_all(b"}")?;
+    }
+    writeln!(out, "]}}")
+}
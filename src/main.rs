@@ -1,16 +1,29 @@
+use std::collections::HashMap;
 use std::fmt::{Debug, Display, Error as FmtErr, Formatter};
 use std::io::{self, BufRead, Write};
 use std::iter::Peekable;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 use termcolor::{
-    Color::{self, Green, Red, White},
-    ColorChoice, ColorSpec, StandardStream, WriteColor,
+    Color::{self, Cyan, Green, Magenta, Red, White, Yellow},
+    ColorChoice, ColorSpec, StandardStream, StandardStreamLock, WriteColor,
 };
 
 use diffr_lib::*;
+use file_header::FileHeaderParser;
+use html::HtmlColorWriter;
+use side_by_side::SideBySide;
+use wrap::StyledLine;
 
 mod cli_args;
+mod compare;
 mod diffr_lib;
+mod file_header;
+mod html;
+mod json;
+mod side_by_side;
+mod terminal;
+mod wrap;
 
 #[derive(Debug, Clone, Copy)]
 pub enum LineNumberStyle {
@@ -18,32 +31,215 @@ pub enum LineNumberStyle {
     Aligned,
 }
 
+#[derive(Debug, Clone, Copy)]
+pub enum Granularity {
+    Char,
+    Word,
+    Subword,
+    Whitespace,
+}
+
+impl Granularity {
+    fn tokenizer(self) -> Box<dyn Tokenizer> {
+        match self {
+            Granularity::Char => Box::new(CharTokenizer),
+            Granularity::Word => Box::new(WordTokenizer),
+            Granularity::Subword => Box::new(SubwordTokenizer),
+            Granularity::Whitespace => Box::new(WhitespaceTokenizer),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum RefineScope {
+    Hunk,
+    Lines,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum FileHeaderStyle {
+    Raw,
+    Banner,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HtmlMode {
+    Document,
+    Fragment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ColorWhen {
+    Auto,
+    Always,
+    Never,
+}
+
+/// The delimiters of the refined segments of the removed and added
+/// lines, when they are marked in the text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Markers {
+    removed: (String, String),
+    added: (String, String),
+}
+
+impl Default for Markers {
+    fn default() -> Self {
+        // like git diff --word-diff=plain
+        Markers {
+            removed: ("[-".to_owned(), "-]".to_owned()),
+            added: ("{+".to_owned(), "+}".to_owned()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum IgnoreWhitespace {
+    All,
+    Amount,
+    Ends,
+}
+
+impl IgnoreWhitespace {
+    /// Remove or merge the whitespace tokens of a line, `tokens[start..]`,
+    /// so that they are compared according to `self`.
+    fn filter(self, data: &[u8], tokens: &mut Vec<(usize, usize)>, start: usize) {
+        let is_blank = |&(lo, hi): &(usize, usize)| is_blank(&data[lo..hi]);
+        let line = tokens.split_off(start);
+        match self {
+            IgnoreWhitespace::All => tokens.extend(line.into_iter().filter(|t| !is_blank(t))),
+            IgnoreWhitespace::Amount => {
+                for token in line {
+                    let merge = start < tokens.len()
+                        && tokens[tokens.len() - 1].1 == token.0
+                        && is_blank(&tokens[tokens.len() - 1])
+                        && is_blank(&token);
+                    if merge {
+                        tokens.last_mut().unwrap().1 = token.1;
+                    } else {
+                        tokens.push(token);
+                    }
+                }
+            }
+            IgnoreWhitespace::Ends => {
+                let is_eol = |&(lo, hi): &(usize, usize)| {
+                    data[lo..hi].iter().all(|&b| b == b'\n' || b == b'\r')
+                };
+                let end = line
+                    .iter()
+                    .rposition(|t| !is_blank(t) && !is_eol(t))
+                    .map_or(0, |i| i + 1);
+                let (content, eol) = line.split_at(end);
+                let content = content.iter().skip_while(|t| is_blank(t));
+                tokens.extend(content.chain(eol.iter().filter(|t| !is_blank(t))));
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct AppConfig {
     debug: bool,
-    html: bool,
+    html: Option<HtmlMode>,
+    /// The URL of the stylesheet of the HTML output, instead of an
+    /// embedded one.
+    html_stylesheet: Option<String>,
     line_numbers_style: Option<LineNumberStyle>,
+    granularity: Granularity,
+    refine_scope: RefineScope,
+    similarity_threshold: f64,
+    algorithm: Algorithm,
+    ignore_whitespace: Option<IgnoreWhitespace>,
+    ignore_case: bool,
+    diff_cost_limit: Option<usize>,
+    hunk_token_limit: Option<usize>,
+    detect_moved: bool,
+    file_header_style: FileHeaderStyle,
+    /// The files to compare, instead of reading a diff from stdin.
+    files: Option<(PathBuf, PathBuf)>,
+    context_lines: usize,
+    recursive: bool,
+    side_by_side: bool,
+    wrap: bool,
+    /// The text starting the rows of the wrapped lines.
+    wrap_glyph: String,
+    /// The width of the output, instead of the width of the terminal.
+    width: Option<usize>,
+    /// The distance between two tab stops, when tabs are expanded to
+    /// spaces.
+    tab_width: Option<usize>,
+    format: OutputFormat,
+    /// Whether the output to the terminal is colored.
+    color: bool,
+    /// The delimiters of the refined segments, if they are marked in the
+    /// text.
+    markers: Option<Markers>,
     added_face: ColorSpec,
     refine_added_face: ColorSpec,
     removed_face: ColorSpec,
     refine_removed_face: ColorSpec,
+    moved_added_face: ColorSpec,
+    moved_removed_face: ColorSpec,
+    file_header_face: ColorSpec,
+    file_header_info_face: ColorSpec,
+    line_number_face: ColorSpec,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         AppConfig {
             debug: false,
-            html: false,
+            html: None,
+            html_stylesheet: None,
             line_numbers_style: None,
+            granularity: Granularity::Word,
+            refine_scope: RefineScope::Lines,
+            similarity_threshold: 0.0,
+            algorithm: Algorithm::Myers,
+            ignore_whitespace: None,
+            ignore_case: false,
+            diff_cost_limit: None,
+            hunk_token_limit: None,
+            detect_moved: false,
+            file_header_style: FileHeaderStyle::Raw,
+            files: None,
+            context_lines: 3,
+            recursive: false,
+            side_by_side: false,
+            wrap: false,
+            wrap_glyph: "↪".to_owned(),
+            width: None,
+            tab_width: None,
+            format: OutputFormat::Text,
+            color: true,
+            markers: None,
             added_face: color_spec(Some(Green), None, false),
             refine_added_face: color_spec(Some(White), Some(Green), true),
             removed_face: color_spec(Some(Red), None, false),
             refine_removed_face: color_spec(Some(White), Some(Red), true),
+            moved_added_face: color_spec(Some(Cyan), None, false),
+            moved_removed_face: color_spec(Some(Magenta), None, false),
+            file_header_face: color_spec(Some(Yellow), None, true),
+            file_header_info_face: color_spec(Some(Yellow), None, false),
+            line_number_face: color_spec(Some(Color::Ansi256(244)), None, false),
         }
     }
 }
 
 impl AppConfig {
+    fn token_normalization(&self) -> TokenNormalization {
+        TokenNormalization {
+            whitespace_amount: matches!(self.ignore_whitespace, Some(IgnoreWhitespace::Amount)),
+            ignore_case: self.ignore_case,
+        }
+    }
+
     fn has_line_numbers(&self) -> bool {
         self.line_numbers_style.is_some()
     }
@@ -54,133 +250,75 @@ impl AppConfig {
         }
         false
     }
-}
 
-struct HtmlColorWriter<W> {
-    inner: W,
-    current_color: Option<ColorSpec>,
-}
+    /// The distance between two tab stops: the one of --tabs, or the
+    /// one of the terminal.
+    fn tab_stop(&self) -> usize {
+        self.tab_width.unwrap_or(MARGIN_TAB_STOP)
+    }
 
-impl<W> HtmlColorWriter<W> {
-    fn new(inner: W) -> Self {
-        HtmlColorWriter {
-            inner,
-            current_color: None,
-        }
+    /// The faces, with the name used to configure them.
+    fn faces(&self) -> [(&'static str, &ColorSpec); 9] {
+        [
+            ("added", &self.added_face),
+            ("refine-added", &self.refine_added_face),
+            ("removed", &self.removed_face),
+            ("refine-removed", &self.refine_removed_face),
+            ("moved-added", &self.moved_added_face),
+            ("moved-removed", &self.moved_removed_face),
+            ("file-header", &self.file_header_face),
+            ("file-header-info", &self.file_header_info_face),
+            ("line-number", &self.line_number_face),
+        ]
     }
-}
 
-fn write_color<W>(w: &mut W, color: &Color) -> std::io::Result<()>
-where
-    W: Write,
-{
-    use Color::*;
-    match color {
-        Black => {
-            w.write(b"black")?;
-        }
-        Blue => {
-            w.write(b"blue")?;
-        }
-        Green => {
-            w.write(b"green")?;
-        }
-        Red => {
-            w.write(b"red")?;
-        }
-        Cyan => {
-            w.write(b"cyan")?;
-        }
-        Magenta => {
-            w.write(b"magenta")?;
-        }
-        Yellow => {
-            w.write(b"yellow")?;
-        }
-        White => {
-            w.write(b"white")?;
-        }
-        Rgb(r, g, b) => {
-            write!(w, "#{:x}{:x}{:x}", r, g, b)?;
+    /// The face of the line numbers of a line with the face `face`: the
+    /// HTML output gives them their own face, to style them apart.
+    fn margin_face<'b>(&'b self, face: &'b ColorSpec) -> &'b ColorSpec {
+        if self.html.is_some() {
+            &self.line_number_face
+        } else {
+            face
         }
-        _ => panic!("not implemented"),
     }
-    Ok(())
 }
 
-impl<W> WriteColor for HtmlColorWriter<W>
-where
-    W: Write,
-{
-    fn supports_color(&self) -> bool {
-        true
-    }
-
-    fn set_color(&mut self, spec: &ColorSpec) -> std::io::Result<()> {
-        if spec.is_none() {
-            self.current_color = None;
-        } else {
-            let w = &mut self.inner;
-            w.write(b"<span style=\"")?;
-            if let Some(fg) = spec.fg() {
-                w.write(b"color:")?;
-                write_color(w, fg)?;
-                w.write(b";")?;
-            }
-            if let Some(bg) = spec.bg() {
-                w.write(b"background:")?;
-                write_color(w, bg)?;
-                w.write(b";")?;
-            }
-            w.write(b"\">")?;
-            self.current_color = Some(spec.clone());
-        }
+/// Where the output is written, with the structure of the document
+/// around the diff.
+trait Output: WriteColor {
+    /// Mark the start of the diff of a file.
+    fn start_file(&mut self) -> io::Result<()> {
         Ok(())
     }
 
-    fn reset(&mut self) -> std::io::Result<()> {
-        if self.current_color.is_some() {
-            self.inner.write(b"</span>")?;
-            self.current_color = None;
-        }
+    /// Write the end of the document.
+    fn finish(&mut self) -> io::Result<()> {
         Ok(())
     }
 }
 
-impl<W> Write for HtmlColorWriter<W>
-where
-    W: Write,
-{
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        let w = &mut self.inner;
-        let n = buf.len();
-        let table: [(u8, &[u8]); 5] = [
-            (b'<', b"&lt;"),
-            (b'>', b"&gt;"),
-            (b'"', b"&quot;"),
-            (b'\'', b"&apos;"),
-            (b'&', b"&amp;"),
-        ];
-        for b in buf {
-            if let Some((_, escaped)) = table.iter().find(|p| p.0 == *b) {
-                w.write(escaped)?;
-            } else {
-                w.write(&[*b])?;
-            }
-        }
-        w.flush()?;
-        Ok(n)
+impl<'a> Output for StandardStreamLock<'a> {}
+
+impl<T: Output + ?Sized> Output for Box<T> {
+    fn start_file(&mut self) -> io::Result<()> {
+        (**self).start_file()
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.inner.flush()
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
     }
 }
 
 fn main() {
     let config = cli_args::parse_config();
     let mut hunk_buffer = HunkBuffer::new(&config);
-    match hunk_buffer.run() {
+    let result = match &config.files {
+        Some((old, new)) => {
+            compare_files(old, new, &config).and_then(|diff| hunk_buffer.run(&diff[..]))
+        }
+        None => hunk_buffer.run(io::stdin().lock()),
+    };
+    match result {
         Ok(()) => (),
         Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => (),
         Err(ref err) => {
@@ -190,6 +328,21 @@ fn main() {
     }
 }
 
+/// The unified diff of the files `old` and `new`, or of all their files
+/// if they are directories compared recursively.
+fn compare_files(old: &Path, new: &Path, config: &AppConfig) -> io::Result<Vec<u8>> {
+    if config.recursive {
+        return compare::diff_directories(old, new, config.context_lines);
+    }
+    compare::unified_diff(
+        &compare::read_file(old)?,
+        &compare::read_file(new)?,
+        &compare::name_bytes(old.as_os_str()),
+        &compare::name_bytes(new.as_os_str()),
+        config.context_lines,
+    )
+}
+
 fn now(do_timings: bool) -> Option<SystemTime> {
     if do_timings {
         Some(SystemTime::now())
@@ -298,82 +451,200 @@ impl ExecStats {
 }
 
 struct HunkBuffer<'a> {
-    v: Vec<isize>,
-    diff_buffer: Vec<Snake>,
+    refinement: Refinement,
     added_tokens: Vec<(usize, usize)>,
     removed_tokens: Vec<(usize, usize)>,
+    added_lines: Vec<(usize, usize)>,
+    removed_lines: Vec<(usize, usize)>,
     line_number_info: Option<HunkHeader>,
     lines: LineSplit,
     config: &'a AppConfig,
     margin: Vec<u8>,
     warning_lines: Vec<usize>,
     stats: ExecStats,
+    tokenizer: Box<dyn Tokenizer>,
+    moved_lines: MovedLines,
+    hunk_start: usize,
+    /// The number of columns of the prefix of the lines of the hunk:
+    /// more than one for the combined diffs of merges.
+    nb_parents: usize,
+    format: HunkFormat,
+    /// The line starting the hunk, for the JSON output.
+    header_line: Vec<u8>,
 }
 
+/// The format of the hunk being buffered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HunkFormat {
+    Unified,
+    /// A hunk of a context diff (`diff -c`), with the index of the
+    /// header of its new section among the lines of the hunk once it
+    /// has been read.
+    Context {
+        new_section: Option<usize>,
+    },
+    /// A command of a normal diff, with its `<` and `>` lines.
+    Normal,
+}
+
+/// How the segments of a changed line are painted: the refined ones
+/// with `highlight`, between the `markers` if any, and the shared ones
+/// with `no_highlight`.
+#[derive(Clone, Copy)]
+struct LinePaint<'a> {
+    no_highlight: &'a ColorSpec,
+    highlight: &'a ColorSpec,
+    markers: Option<&'a (String, String)>,
+}
+
+/// The changed lines of the input belonging to a moved block, by index
+/// in the input, with the segments they share with their counterpart
+/// (relative to the start of the line).
+type MovedLines = HashMap<usize, Vec<(usize, usize)>>;
+
 #[derive(Default)]
 struct Margin<'a> {
-    lino_minus: usize,
+    // one line number per parent
+    lino_minus: Vec<usize>,
     lino_plus: usize,
     margin: &'a mut [u8],
-    half_margin: usize,
+    column_width: usize,
 }
 
 const MARGIN_TAB_STOP: usize = 8;
 
 impl<'a> Margin<'a> {
     fn new(header: &'a HunkHeader, margin: &'a mut [u8]) -> Self {
-        let full_margin = header.width();
-        let half_margin = full_margin / 2;
+        let column_width = header.column_width();
 
         // If line number is 0, the column is empty and
         // shouldn't be printed
-        let margin_size = if header.minus_range.0 == 0 || header.plus_range.0 == 0 {
-            half_margin
-        } else {
-            full_margin
-        };
+        let nb_columns = header
+            .minus_ranges
+            .iter()
+            .chain(Some(&header.plus_range))
+            .filter(|range| range.0 != 0)
+            .count();
+        let margin_size = (nb_columns * (column_width + 1)).saturating_sub(1);
         assert!(margin.len() >= margin_size);
         Margin {
             lino_plus: header.plus_range.0,
-            lino_minus: header.minus_range.0,
+            lino_minus: header.minus_ranges.iter().map(|range| range.0).collect(),
             margin: &mut margin[..margin_size],
-            half_margin,
+            column_width,
+        }
+    }
+
+    /// The number of characters taken by the margin.
+    fn width(&self, config: &AppConfig) -> usize {
+        let len = self.margin.len();
+        let tab_stop = config.tab_stop();
+        match len % tab_stop {
+            // the padding goes to the next tab stop
+            rem if config.line_numbers_aligned() && rem != 0 => len + tab_stop - rem,
+            _ => len,
         }
     }
 
-    fn write_margin_padding(&mut self, out: &mut impl WriteColor) -> io::Result<()> {
-        if self.margin.len() % MARGIN_TAB_STOP != 0 {
-            write!(out, "\t")?;
+    fn write_margin_padding(
+        &mut self,
+        config: &AppConfig,
+        out: &mut impl WriteColor,
+    ) -> io::Result<()> {
+        let len = self.margin.len();
+        match config.tab_width {
+            // expanded tabs are spaces up to the next tab stop
+            Some(_) => write!(out, "{:w$}", "", w = self.width(config) - len)?,
+            None if !len.is_multiple_of(MARGIN_TAB_STOP) => write!(out, "\t")?,
+            None => (),
         }
         Ok(())
     }
 
+    /// Write the margin of a changed line, whose prefix has one column
+    /// per parent.
     fn write_margin_changed(
         &mut self,
-        is_plus: bool,
+        prefix: &[u8],
         config: &AppConfig,
         out: &mut impl WriteColor,
     ) -> io::Result<()> {
-        let mut margin_buf = &mut self.margin[..];
-        let color;
-        if is_plus {
-            color = &config.added_face;
-            if self.lino_minus != 0 {
-                write!(margin_buf, "{:w$} ", ' ', w = self.half_margin)?;
+        // The line is in the result unless it was removed, and in a
+        // parent if it was removed from it, or if it is in the result
+        // without having been added to it.
+        let is_plus = !prefix.contains(&b'-');
+        let in_parents = (0..self.lino_minus.len())
+            .map(|parent| match prefix.get(parent) {
+                Some(b'-') => true,
+                Some(b'+') => false,
+                _ => is_plus,
+            })
+            .collect::<Vec<_>>();
+        let color = if is_plus {
+            &config.added_face
+        } else {
+            &config.removed_face
+        };
+        self.write_margin_columns(&in_parents, is_plus, color, config, out)
+    }
+
+    /// Write the margin of a line of a context diff, which only belongs
+    /// to the old or to the new file depending on its section.
+    fn write_margin_section(
+        &mut self,
+        in_new_section: bool,
+        color: &ColorSpec,
+        config: &AppConfig,
+        out: &mut impl WriteColor,
+    ) -> io::Result<()> {
+        self.write_margin_columns(&[!in_new_section], in_new_section, color, config, out)
+    }
+
+    /// Write the line numbers of a line belonging to the parents of
+    /// `in_parents`, and to the result if `in_result`, leaving the
+    /// other columns blank.
+    fn write_margin_columns(
+        &mut self,
+        in_parents: &[bool],
+        in_result: bool,
+        color: &ColorSpec,
+        config: &AppConfig,
+        out: &mut impl WriteColor,
+    ) -> io::Result<()> {
+        let mut columns = vec![];
+        for (lino, &in_parent) in self.lino_minus.iter_mut().zip(in_parents) {
+            if *lino != 0 {
+                columns.push(if in_parent { Some(*lino) } else { None });
             }
-            write!(margin_buf, "{:w$}", self.lino_plus, w = self.half_margin)?;
+            if in_parent {
+                *lino += 1;
+            }
+        }
+        if self.lino_plus != 0 {
+            columns.push(if in_result {
+                Some(self.lino_plus)
+            } else {
+                None
+            });
+        }
+        if in_result {
             self.lino_plus += 1;
-        } else {
-            color = &config.removed_face;
-            write!(margin_buf, "{:w$}", self.lino_minus, w = self.half_margin)?;
-            if self.lino_plus != 0 {
-                write!(margin_buf, " {:w$}", ' ', w = self.half_margin)?;
+        }
+
+        let mut margin_buf = &mut self.margin[..];
+        for (i, column) in columns.into_iter().enumerate() {
+            if i != 0 {
+                write!(margin_buf, " ")?;
             }
-            self.lino_minus += 1;
-        };
+            match column {
+                Some(lino) => write!(margin_buf, "{:w$}", lino, w = self.column_width)?,
+                None => write!(margin_buf, "{:w$}", ' ', w = self.column_width)?,
+            }
+        }
+        let color = config.margin_face(color);
         output(self.margin, 0, self.margin.len(), color, out)?;
         if config.line_numbers_aligned() {
-            self.write_margin_padding(out)?;
+            self.write_margin_padding(config, out)?;
         }
         Ok(())
     }
@@ -383,16 +654,25 @@ impl<'a> Margin<'a> {
         config: &AppConfig,
         out: &mut impl WriteColor,
     ) -> io::Result<()> {
-        if self.lino_minus != self.lino_plus {
-            write!(out, "{:w$}", self.lino_minus, w = self.half_margin)?;
+        let mut margin = String::new();
+        for lino in &mut self.lino_minus {
+            if *lino != self.lino_plus {
+                margin.push_str(&format!("{:w$} ", lino, w = self.column_width));
+            } else {
+                margin.push_str(&format!("{:w$} ", ' ', w = self.column_width));
+            }
+            *lino += 1;
+        }
+        margin.push_str(&format!("{:w$}", self.lino_plus, w = self.column_width));
+        if config.html.is_some() {
+            let face = &config.line_number_face;
+            output(margin.as_bytes(), 0, margin.len(), face, out)?;
         } else {
-            write!(out, "{:w$}", ' ', w = self.half_margin)?;
+            out.write_all(margin.as_bytes())?;
         }
-        write!(out, " {:w$}", self.lino_plus, w = self.half_margin)?;
         if config.line_numbers_aligned() {
-            self.write_margin_padding(out)?;
+            self.write_margin_padding(config, out)?;
         }
-        self.lino_minus += 1;
         self.lino_plus += 1;
         Ok(())
     }
@@ -408,22 +688,503 @@ fn shared_spans(added_tokens: &Tokenization, diff_buffer: &Vec<Snake>) -> Vec<(u
     shared_spans
 }
 
+/// Scratch buffers and results of the word-level diff of a hunk.
+struct Refinement {
+    v: Vec<isize>,
+    diff_buffer: Vec<Snake>,
+    shared_added: Vec<(usize, usize)>,
+    shared_removed: Vec<(usize, usize)>,
+    /// The indexes of the removed and added lines paired with each
+    /// other, when lines are refined by pairs.
+    pairs: Vec<(usize, usize)>,
+    similarity_threshold: f64,
+    algorithm: Algorithm,
+    cost_limit: Option<usize>,
+}
+
+impl Refinement {
+    fn new(config: &AppConfig) -> Self {
+        Refinement {
+            v: vec![],
+            diff_buffer: vec![],
+            shared_added: vec![],
+            shared_removed: vec![],
+            pairs: vec![],
+            similarity_threshold: config.similarity_threshold,
+            algorithm: config.algorithm,
+            cost_limit: config.diff_cost_limit,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.shared_added.clear();
+        self.shared_removed.clear();
+        self.pairs.clear();
+    }
+
+    /// Consider all the `removed` and `added` tokens shared, so that
+    /// they are not refined.
+    fn skip(&mut self, removed_spans: &[(usize, usize)], added_spans: &[(usize, usize)]) {
+        for (spans, shared) in &mut [
+            (removed_spans, &mut self.shared_removed),
+            (added_spans, &mut self.shared_added),
+        ] {
+            if let (Some(first), Some(last)) = (spans.first(), spans.last()) {
+                shared.push((first.0, last.1));
+            }
+        }
+    }
+
+    /// Compute the shared segments between the `removed` and `added`
+    /// tokens, and append them to `self.shared_removed` and
+    /// `self.shared_added`.
+    ///
+    /// If the proportion of shared tokens is below the similarity
+    /// threshold, all the tokens are considered shared, so that
+    /// unrelated lines are not refined.
+    fn refine<'a>(
+        &mut self,
+        data: &'a [u8],
+        m: &TokenMap<'a>,
+        removed_spans: &'a [(usize, usize)],
+        added_spans: &'a [(usize, usize)],
+        stats: &mut ExecStats,
+    ) {
+        let removed = Tokenization::new(data, removed_spans, m);
+        let added = Tokenization::new(data, added_spans, m);
+        let mut tokens = DiffInput::new(&added, &removed);
+        if let Some(limit) = self.cost_limit {
+            tokens = tokens.cost_limit(limit);
+        }
+        let start = now(stats.do_timings());
+        self.algorithm
+            .diff(&tokens, &mut self.v, &mut self.diff_buffer);
+        let nb_shared = self.diff_buffer.iter().map(|s| s.len).sum::<isize>();
+        let nb_tokens = tokens.removed().nb_tokens() + tokens.added().nb_tokens();
+        if nb_tokens != 0
+            && 2.0 * (nb_shared as f64) < self.similarity_threshold * (nb_tokens as f64)
+        {
+            stats.time_lcs_ms += duration_ms_since(&start);
+            self.skip(removed_spans, added_spans);
+            return;
+        }
+        // TODO output the lcs directly out of `diff` instead
+        let (added, removed) = (tokens.added(), tokens.removed());
+        let shared_spans = shared_spans(added, &self.diff_buffer);
+        let lcs = Tokenization::new(added.data(), &shared_spans, m);
+        stats.time_lcs_ms += duration_ms_since(&start);
+        let start = now(stats.do_timings());
+        let normalized_lcs_added = optimize_partition(added, &lcs);
+        let normalized_lcs_removed = optimize_partition(removed, &lcs);
+        stats.time_opt_lcs_ms += duration_ms_since(&start);
+        self.shared_added
+            .extend(normalized_lcs_added.shared_segments(added));
+        self.shared_removed
+            .extend(normalized_lcs_removed.shared_segments(removed));
+    }
+
+    /// Refine the changed lines of a hunk relative to one parent.
+    fn refine_hunk<'a>(
+        &mut self,
+        scope: RefineScope,
+        data: &'a [u8],
+        m: &TokenMap<'a>,
+        view: &HunkView<'a>,
+        stats: &mut ExecStats,
+    ) {
+        let HunkView {
+            blocks,
+            removed_tokens,
+            removed_lines,
+            added_tokens,
+            added_lines,
+        } = *view;
+        match scope {
+            RefineScope::Hunk => self.refine(data, m, removed_tokens, added_tokens, stats),
+            RefineScope::Lines => {
+                let removed = Tokenization::new(data, removed_tokens, m);
+                let added = Tokenization::new(data, added_tokens, m);
+                for block in blocks {
+                    let removed_ids = removed_lines[block.removed.0..block.removed.1]
+                        .iter()
+                        .map(|&(lo, hi)| &removed.tokens()[lo..hi])
+                        .collect::<Vec<_>>();
+                    let added_ids = added_lines[block.added.0..block.added.1]
+                        .iter()
+                        .map(|&(lo, hi)| &added.tokens()[lo..hi])
+                        .collect::<Vec<_>>();
+                    let pairs = align_lines(&removed_ids, &added_ids);
+                    self.pairs.extend(
+                        pairs
+                            .iter()
+                            .map(|&(i, j)| (block.removed.0 + i, block.added.0 + j)),
+                    );
+
+                    // Refine each pair of lines on its own, and the
+                    // lines between two pairs as a block.
+                    let mut refine = |removed_range, added_range| {
+                        let removed = tokens_of_lines(removed_tokens, removed_lines, removed_range);
+                        let added = tokens_of_lines(added_tokens, added_lines, added_range);
+                        if !removed.is_empty() && !added.is_empty() {
+                            self.refine(data, m, removed, added, stats)
+                        }
+                    };
+                    let (mut i0, mut j0) = (block.removed.0, block.added.0);
+                    for (i, j) in pairs {
+                        let (i, j) = (block.removed.0 + i, block.added.0 + j);
+                        refine((i0, i), (j0, j));
+                        refine((i, i + 1), (j, j + 1));
+                        i0 = i + 1;
+                        j0 = j + 1;
+                    }
+                    refine((i0, block.removed.1), (j0, block.added.1));
+                }
+            }
+        }
+    }
+
+    /// Sort the shared segments and merge those overlapping, once a
+    /// hunk has been refined relative to several parents.
+    fn merge_shared(&mut self) {
+        for shared in [&mut self.shared_removed, &mut self.shared_added] {
+            shared.sort_unstable();
+            let mut merged: Vec<(usize, usize)> = vec![];
+            for &(lo, hi) in shared.iter() {
+                match merged.last_mut() {
+                    Some(last) if lo <= last.1 => last.1 = last.1.max(hi),
+                    _ => merged.push((lo, hi)),
+                }
+            }
+            *shared = merged;
+        }
+    }
+}
+
+/// The changed lines of a hunk relative to one parent: its blocks of
+/// changed lines, and the tokens of the removed and added lines with the
+/// range of tokens of each line.
+#[derive(Clone, Copy)]
+struct HunkView<'a> {
+    blocks: &'a [ChangeBlock],
+    removed_tokens: &'a [(usize, usize)],
+    removed_lines: &'a [(usize, usize)],
+    added_tokens: &'a [(usize, usize)],
+    added_lines: &'a [(usize, usize)],
+}
+
+/// A group of consecutive changed lines: the ranges of indexes of its
+/// removed and added lines.
+struct ChangeBlock {
+    removed: (usize, usize),
+    added: (usize, usize),
+}
+
+/// How a line of a hunk relates to a parent of the diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineKind {
+    Removed,
+    Added,
+    Context,
+    /// Neither in the parent nor in the result, like the warnings of
+    /// missing newlines.
+    Absent,
+}
+
+/// The kind of a line of a hunk relative to the parent of index
+/// `parent`, from the prefix of the line: a diff has a single column of
+/// prefix, the combined diff of a merge has one column per parent.
+fn line_kind(prefix: &[u8], parent: usize) -> LineKind {
+    match prefix.get(parent) {
+        Some(b'-') => LineKind::Removed,
+        Some(b'+') => LineKind::Added,
+        Some(b' ') if !prefix.contains(&b'-') => LineKind::Context,
+        _ => LineKind::Absent,
+    }
+}
+
+/// The kind of a line of a hunk relative to all the parents, or None
+/// if the prefix does not belong to a hunk.
+fn prefix_kind(prefix: &[u8]) -> Option<LineKind> {
+    if prefix.first() == Some(&b'\\') {
+        Some(LineKind::Absent)
+    } else if prefix.is_empty() || !prefix.iter().all(|b| b" +-".contains(b)) {
+        None
+    } else if prefix.contains(&b'+') {
+        Some(LineKind::Added)
+    } else if prefix.contains(&b'-') {
+        Some(LineKind::Removed)
+    } else {
+        Some(LineKind::Context)
+    }
+}
+
+/// The kind of a raw line of a hunk of `nb_parents` parents relative to
+/// all of them, or None if the line ends the hunk.
+fn hunk_line_kind(line: &[u8], nb_parents: usize) -> Option<LineKind> {
+    let sanitized;
+    let prefix = if nb_parents == 1 {
+        let nbytes = skip_all_escape_code(line);
+        line.get(nbytes..nbytes + 1)?
+    } else {
+        sanitized = strip_escape_codes(line);
+        match sanitized.get(..nb_parents) {
+            Some(prefix) => prefix,
+            None if sanitized.first() == Some(&b'\\') => &sanitized[..1],
+            None => return None,
+        }
+    };
+    prefix_kind(prefix)
+}
+
+/// Split the lines of a hunk into blocks of changed lines, from the
+/// kinds of its lines relative to a parent.
+fn change_blocks(kinds: &[LineKind]) -> Vec<ChangeBlock> {
+    let mut blocks = vec![];
+    let mut start = (0, 0);
+    let mut end = (0, 0);
+    for kind in kinds {
+        match kind {
+            LineKind::Removed => end.0 += 1,
+            LineKind::Added => end.1 += 1,
+            LineKind::Absent => (),
+            LineKind::Context => {
+                if start != end {
+                    blocks.push(ChangeBlock {
+                        removed: (start.0, end.0),
+                        added: (start.1, end.1),
+                    });
+                }
+                start = end;
+            }
+        }
+    }
+    if start != end {
+        blocks.push(ChangeBlock {
+            removed: (start.0, end.0),
+            added: (start.1, end.1),
+        });
+    }
+    blocks
+}
+
+/// The kind of a line of a hunk of a context diff (`diff -c`) from its
+/// first byte, or None if the line ends the hunk. A hunk of a context
+/// diff shows the old lines, then the new lines after the header of its
+/// new section: `!` marks changed lines in both sections.
+fn context_line_kind(first: u8, in_new_section: bool) -> Option<LineKind> {
+    match first {
+        b'!' if in_new_section => Some(LineKind::Added),
+        b'!' => Some(LineKind::Removed),
+        b'-' if !in_new_section => Some(LineKind::Removed),
+        b'+' if in_new_section => Some(LineKind::Added),
+        b' ' => Some(LineKind::Context),
+        b'\\' => Some(LineKind::Absent),
+        _ => None,
+    }
+}
+
+/// Split the lines of a hunk of a context diff into blocks of changed
+/// lines: the n-th group of `!` lines of the old section is paired with
+/// the n-th group of the new section, and the groups of removed or added
+/// lines form blocks of their own.
+fn context_change_blocks(
+    data: &[u8],
+    lines: &LineSplit,
+    kinds: &[Option<LineKind>],
+) -> Vec<ChangeBlock> {
+    /// Consecutive changed lines with the same marker.
+    struct Group {
+        marker: u8,
+        kind: LineKind,
+        lines: (usize, usize),
+    }
+
+    let mut groups: Vec<Group> = vec![];
+    let mut nb_lines = (0, 0);
+    for (range, &kind) in lines.iter().zip(kinds) {
+        let marker = data[range.0];
+        let (kind, index) = match kind {
+            Some(kind @ LineKind::Removed) => (kind, &mut nb_lines.0),
+            Some(kind @ LineKind::Added) => (kind, &mut nb_lines.1),
+            _ => continue,
+        };
+        match groups.last_mut() {
+            Some(group)
+                if group.marker == marker && group.kind == kind && group.lines.1 == *index =>
+            {
+                group.lines.1 += 1
+            }
+            _ => groups.push(Group {
+                marker,
+                kind,
+                lines: (*index, *index + 1),
+            }),
+        }
+        *index += 1;
+    }
+
+    let block = |removed, added| ChangeBlock { removed, added };
+    let changed = |kind| {
+        groups
+            .iter()
+            .filter(move |group| group.marker == b'!' && group.kind == kind)
+            .map(|group| group.lines)
+    };
+    let mut blocks = vec![];
+    let mut added_changed = changed(LineKind::Added);
+    for removed in changed(LineKind::Removed) {
+        blocks.push(block(removed, added_changed.next().unwrap_or((0, 0))));
+    }
+    for added in added_changed {
+        blocks.push(block((0, 0), added));
+    }
+    for group in groups.iter().filter(|group| group.marker != b'!') {
+        blocks.push(match group.kind {
+            LineKind::Removed => block(group.lines, (0, 0)),
+            _ => block((0, 0), group.lines),
+        });
+    }
+    blocks
+}
+
+/// `line` without its end of line.
+fn strip_newline(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\n")
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .unwrap_or(line)
+}
+
+/// A range of lines written `first,last` or `first`, like in context
+/// and normal diffs, as (ofs, len).
+fn parse_inclusive_range(range: &[u8]) -> Option<(usize, usize)> {
+    let number = |digits: &[u8]| -> Option<usize> {
+        if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        std::str::from_utf8(digits).ok()?.parse().ok()
+    };
+    let mut bounds = range.splitn(2, |&b| b == b',');
+    let first = number(bounds.next()?)?;
+    match bounds.next() {
+        Some(last) => Some((first, (number(last)? + 1).checked_sub(first)?)),
+        None if first == 0 => Some((0, 0)),
+        None => Some((first, 1)),
+    }
+}
+
+/// The range of the header of a section of a hunk of a context diff,
+/// like `*** 1,5 ****` or `--- 3 ----`, as (ofs, len).
+fn parse_context_range(line: &[u8], start: &[u8], end: &[u8]) -> Option<(usize, usize)> {
+    let range = strip_newline(line)
+        .strip_prefix(start)?
+        .strip_prefix(b" ")?
+        .strip_suffix(end)?
+        .strip_suffix(b" ")?;
+    parse_inclusive_range(range)
+}
+
+/// The kind of a line of a hunk of a normal diff (the default output of
+/// `diff`), or None if the line ends the hunk. The `---` separating the
+/// removed and added lines of a change is kept as is.
+fn normal_line_kind(line: &[u8]) -> Option<LineKind> {
+    match line.first()? {
+        b'<' => Some(LineKind::Removed),
+        b'>' => Some(LineKind::Added),
+        b'\\' => Some(LineKind::Absent),
+        b'-' if strip_newline(line) == b"---" => Some(LineKind::Absent),
+        _ => None,
+    }
+}
+
+/// The ranges of a command of a normal diff, like `5,7c5,6`, `3a4` or
+/// `8d7`, which starts a hunk.
+fn parse_normal_command(line: &[u8]) -> Option<HunkHeader> {
+    let line = strip_newline(line);
+    let op = line.iter().position(|b| b"acd".contains(b))?;
+    let minus_range = parse_inclusive_range(&line[..op])?;
+    let plus_range = parse_inclusive_range(&line[op + 1..])?;
+    // the side without lines gives the line after which they would be
+    let (minus_range, plus_range) = match line[op] {
+        b'a' => ((minus_range.0, 0), plus_range),
+        b'd' => (minus_range, (plus_range.0, 0)),
+        _ => (minus_range, plus_range),
+    };
+    Some(HunkHeader::new(vec![minus_range], plus_range))
+}
+
+/// The tokens of some changed lines, and the range of tokens of each
+/// line.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ChangedLines {
+    tokens: Vec<(usize, usize)>,
+    lines: Vec<(usize, usize)>,
+}
+
+/// The changed lines of kind `side` of a combined hunk that are changed
+/// relative to the parent whose kinds of lines are `kinds`. `tokens`
+/// and `lines` hold all the changed lines of kind `side` of the hunk,
+/// whose kinds relative to all the parents are `hunk_kinds`.
+fn parent_lines(
+    hunk_kinds: &[Option<LineKind>],
+    kinds: &[LineKind],
+    side: LineKind,
+    tokens: &[(usize, usize)],
+    lines: &[(usize, usize)],
+) -> ChangedLines {
+    let mut result = ChangedLines::default();
+    let mut side_lines = lines.iter();
+    for (hunk_kind, kind) in hunk_kinds.iter().zip(kinds) {
+        if *hunk_kind != Some(side) {
+            continue;
+        }
+        if let Some(&(lo, hi)) = side_lines.next() {
+            if *kind == side {
+                let first_token = result.tokens.len();
+                result.tokens.extend_from_slice(&tokens[lo..hi]);
+                result.lines.push((first_token, result.tokens.len()));
+            }
+        }
+    }
+    result
+}
+
+/// The tokens of the lines `lo..hi`, where `lines` holds the range of
+/// token indexes of each line.
+fn tokens_of_lines<'a>(
+    tokens: &'a [(usize, usize)],
+    lines: &[(usize, usize)],
+    (lo, hi): (usize, usize),
+) -> &'a [(usize, usize)] {
+    if lo < hi {
+        &tokens[lines[lo].0..lines[hi - 1].1]
+    } else {
+        &[]
+    }
+}
+
 const MAX_MARGIN: usize = 41;
 
 impl<'a> HunkBuffer<'a> {
     fn new(config: &'a AppConfig) -> Self {
         let debug = config.debug;
         HunkBuffer {
-            v: vec![],
-            diff_buffer: vec![],
+            refinement: Refinement::new(config),
             added_tokens: vec![],
             removed_tokens: vec![],
+            added_lines: vec![],
+            removed_lines: vec![],
             line_number_info: None,
             lines: Default::default(),
             config,
             margin: vec![0; MAX_MARGIN],
             warning_lines: vec![],
             stats: ExecStats::new(debug),
+            tokenizer: config.granularity.tokenizer(),
+            moved_lines: HashMap::new(),
+            hunk_start: 0,
+            nb_parents: 1,
+            format: HunkFormat::Unified,
+            header_line: vec![],
         }
     }
 
@@ -431,8 +1192,8 @@ impl<'a> HunkBuffer<'a> {
     fn paint_line<Stream, Positions>(
         data: &[u8],
         &(data_lo, data_hi): &(usize, usize),
-        no_highlight: &ColorSpec,
-        highlight: &ColorSpec,
+        prefix_len: usize,
+        paint: &LinePaint,
         shared: &mut Peekable<Positions>,
         out: &mut Stream,
     ) -> io::Result<()>
@@ -440,7 +1201,12 @@ impl<'a> HunkBuffer<'a> {
         Stream: WriteColor,
         Positions: Iterator<Item = (usize, usize)>,
     {
-        let mut y = data_lo + 1;
+        let LinePaint {
+            no_highlight,
+            highlight,
+            markers,
+        } = *paint;
+        let mut y = data_lo + prefix_len;
         // XXX: skip leading token and leading spaces
         while y < data_hi && data[y].is_ascii_whitespace() {
             y += 1
@@ -448,27 +1214,33 @@ impl<'a> HunkBuffer<'a> {
         let mut pending = (data_lo, y, false);
         let mut trailing_ws = ColorSpec::new();
         trailing_ws.set_bg(Some(Color::Red));
-        let color = |h| if h { &highlight } else { &no_highlight };
+        let color = |h| if h { highlight } else { no_highlight };
+        let markers = |h| if h { markers } else { None };
         let mut output1 = |lo, hi, highlighted| -> std::io::Result<()> {
             if lo == hi {
                 return Ok(());
             }
             let (lo1, hi1, highlighted1) = pending;
-            let color = if &data[lo..hi] == b"\n"
+            if markers(highlighted).is_some() && highlighted == highlighted1 && lo == hi1 {
+                // one pair of markers around consecutive refined segments
+                pending = (lo1, hi, highlighted);
+                return Ok(());
+            }
+            let (color, markers) = if &data[lo..hi] == b"\n"
                 && data[lo1..hi1].iter().all(|b| b.is_ascii_whitespace())
             {
-                &trailing_ws
+                (&trailing_ws, None)
             } else {
-                color(highlighted1)
+                (color(highlighted1), markers(highlighted1))
             };
-            output(data, lo1, hi1, color, out)?;
+            output_marked(data, lo1, hi1, color, markers, out)?;
             pending = (lo, hi, highlighted);
             Ok(())
         };
         // special case: all whitespaces
         if y == data_hi {
-            output(data, data_lo, data_lo + 1, &no_highlight, out)?;
-            output(data, data_lo + 1, data_hi, &trailing_ws, out)?;
+            output(data, data_lo, data_lo + prefix_len, no_highlight, out)?;
+            output(data, data_lo + prefix_len, data_hi, &trailing_ws, out)?;
             return Ok(());
         }
 
@@ -497,7 +1269,8 @@ impl<'a> HunkBuffer<'a> {
         }
         output1(y, data_hi, true)?;
         let (lo1, hi1, highlighted1) = pending;
-        output(data, lo1, hi1, color(highlighted1), out)?;
+        let markers = markers(highlighted1);
+        output_marked(data, lo1, hi1, color(highlighted1), markers, out)?;
         Ok(())
     }
 
@@ -516,88 +1289,389 @@ impl<'a> HunkBuffer<'a> {
         Stream: WriteColor,
     {
         let Self {
-            v,
-            diff_buffer,
+            refinement,
             added_tokens,
             removed_tokens,
+            added_lines,
+            removed_lines,
             line_number_info,
             lines,
             config,
             margin,
             warning_lines,
             stats,
+            tokenizer: _,
+            moved_lines,
+            hunk_start,
+            nb_parents,
+            format,
+            header_line,
         } = self;
         let mut margin = match line_number_info {
-            Some(lni) => Margin::new(lni, margin),
+            Some(lni) => {
+                if margin.len() < lni.width() {
+                    margin.resize(lni.width(), 0);
+                }
+                Margin::new(lni, margin)
+            }
             None => Default::default(),
         };
         let data = lines.data();
-        let m = TokenMap::new(&mut [(removed_tokens.iter(), data), (added_tokens.iter(), data)]);
-        let removed = Tokenization::new(data, removed_tokens, &m);
-        let added = Tokenization::new(data, added_tokens, &m);
-        let tokens = DiffInput::new(&added, &removed);
-        let start = now(stats.do_timings());
-        diffr_lib::diff(&tokens, v, diff_buffer);
-        // TODO output the lcs directly out of `diff` instead
-        let shared_spans = shared_spans(&added, &diff_buffer);
-        let lcs = Tokenization::new(data, &shared_spans, &m);
-        stats.time_lcs_ms += duration_ms_since(&start);
-        let start = now(stats.do_timings());
-        let normalized_lcs_added = optimize_partition(&added, &lcs);
-        let normalized_lcs_removed = optimize_partition(&removed, &lcs);
-        stats.time_opt_lcs_ms += duration_ms_since(&start);
-        let mut shared_added = normalized_lcs_added.shared_segments(&added).peekable();
-        let mut shared_removed = normalized_lcs_removed.shared_segments(&removed).peekable();
+        let m = TokenMap::new(
+            &mut [(removed_tokens.iter(), data), (added_tokens.iter(), data)],
+            config.token_normalization(),
+        );
+        refinement.clear();
+        let nb_parents = *nb_parents;
+        let format = *format;
+        let prefix = |&(lo, hi): &(usize, usize)| &data[lo..(lo + nb_parents).min(hi)];
+        // the number of characters before the content of the lines
+        let prefix_len = match format {
+            HunkFormat::Unified => nb_parents,
+            HunkFormat::Context { .. } | HunkFormat::Normal => 2,
+        };
+        let in_new_section = |i| match format {
+            HunkFormat::Context { new_section } => matches!(new_section, Some(s) if s < i),
+            HunkFormat::Unified | HunkFormat::Normal => false,
+        };
+        // the kind of each line relative to all the parents
+        let hunk_kinds = lines
+            .iter()
+            .enumerate()
+            .map(|(i, range)| match format {
+                HunkFormat::Unified => prefix_kind(prefix(&range)),
+                HunkFormat::Context { new_section } if new_section == Some(i) => {
+                    Some(LineKind::Absent)
+                }
+                HunkFormat::Context { .. } => context_line_kind(data[range.0], in_new_section(i)),
+                HunkFormat::Normal => normal_line_kind(&data[range.0..range.1]),
+            })
+            .collect::<Vec<_>>();
+        let kinds = |parent| {
+            lines
+                .iter()
+                .map(|range| line_kind(prefix(&range), parent))
+                .collect::<Vec<_>>()
+        };
+        let nb_tokens = removed_tokens.len() + added_tokens.len();
+        if matches!(config.hunk_token_limit, Some(limit) if limit < nb_tokens) {
+            refinement.skip(removed_tokens, added_tokens)
+        } else if nb_parents == 1 {
+            let blocks = match format {
+                HunkFormat::Unified => change_blocks(&kinds(0)),
+                HunkFormat::Context { .. } => context_change_blocks(data, lines, &hunk_kinds),
+                HunkFormat::Normal => change_blocks(
+                    &hunk_kinds
+                        .iter()
+                        .map(|kind| kind.unwrap_or(LineKind::Absent))
+                        .collect::<Vec<_>>(),
+                ),
+            };
+            let view = HunkView {
+                blocks: &blocks,
+                removed_tokens,
+                removed_lines,
+                added_tokens,
+                added_lines,
+            };
+            refinement.refine_hunk(config.refine_scope, data, &m, &view, stats);
+            if let HunkFormat::Context { .. } = format {
+                refinement.merge_shared();
+            }
+        } else {
+            // refine relative to each parent in turn: the segments
+            // shared with any parent are not highlighted
+            for parent in 0..nb_parents {
+                let kinds = kinds(parent);
+                let removed = parent_lines(
+                    &hunk_kinds,
+                    &kinds,
+                    LineKind::Removed,
+                    removed_tokens,
+                    removed_lines,
+                );
+                let added = parent_lines(
+                    &hunk_kinds,
+                    &kinds,
+                    LineKind::Added,
+                    added_tokens,
+                    added_lines,
+                );
+                let view = HunkView {
+                    blocks: &change_blocks(&kinds),
+                    removed_tokens: &removed.tokens,
+                    removed_lines: &removed.lines,
+                    added_tokens: &added.tokens,
+                    added_lines: &added.lines,
+                };
+                refinement.refine_hunk(config.refine_scope, data, &m, &view, stats);
+            }
+            refinement.merge_shared();
+        }
+        let mut shared_added = refinement.shared_added.iter().cloned().peekable();
+        let mut shared_removed = refinement.shared_removed.iter().cloned().peekable();
         let mut warnings = warning_lines.iter().peekable();
         let defaultspec = ColorSpec::default();
+        let mut paint_changed =
+            |i: usize, range: (usize, usize), is_plus: bool, mut out: &mut dyn WriteColor| {
+                let markers = config.markers.as_ref();
+                let (mut paint, shared) = if is_plus {
+                    (
+                        LinePaint {
+                            no_highlight: &config.added_face,
+                            highlight: &config.refine_added_face,
+                            markers: markers.map(|markers| &markers.added),
+                        },
+                        &mut shared_added,
+                    )
+                } else {
+                    (
+                        LinePaint {
+                            no_highlight: &config.removed_face,
+                            highlight: &config.refine_removed_face,
+                            markers: markers.map(|markers| &markers.removed),
+                        },
+                        &mut shared_removed,
+                    )
+                };
+                if let Some(segments) = moved_lines.get(&(*hunk_start + i)) {
+                    paint.no_highlight = if is_plus {
+                        &config.moved_added_face
+                    } else {
+                        &config.moved_removed_face
+                    };
+                    let mut shared = segments
+                        .iter()
+                        .map(|&(lo, hi)| (range.0 + lo, range.0 + hi))
+                        .peekable();
+                    Self::paint_line(data, &range, nb_parents, &paint, &mut shared, &mut out)
+                } else {
+                    Self::paint_line(data, &range, nb_parents, &paint, shared, &mut out)
+                }
+            };
 
-        for (i, range) in lines.iter().enumerate() {
-            if let Some(&&nline) = warnings.peek() {
-                if nline == i {
-                    let w = &lines.data()[range.0..range.1];
-                    output(w, 0, w.len(), &defaultspec, out)?;
-                    warnings.next();
-                    continue;
+        let side_by_side =
+            config.side_by_side && nb_parents == 1 && !matches!(format, HunkFormat::Context { .. });
+        if config.format == OutputFormat::Json {
+            let mut json_lines = vec![];
+            for (i, range) in lines.iter().enumerate() {
+                let end = if data[..range.1].ends_with(b"\n") {
+                    range.1 - 1
+                } else {
+                    range.1
+                };
+                let start = (range.0 + prefix_len).min(end);
+                let mut line = json::Line {
+                    kind: "context",
+                    text: &data[start..end],
+                    segments: None,
+                    moved: false,
+                };
+                match hunk_kinds[i] {
+                    Some(kind @ LineKind::Removed) | Some(kind @ LineKind::Added) => {
+                        let moved = moved_lines.get(&(*hunk_start + i));
+                        let shared = match moved {
+                            Some(segments) => segments
+                                .iter()
+                                .map(|&(lo, hi)| (range.0 + lo, range.0 + hi))
+                                .collect(),
+                            None => {
+                                let shared = if kind == LineKind::Added {
+                                    &refinement.shared_added
+                                } else {
+                                    &refinement.shared_removed
+                                };
+                                let first = shared.partition_point(|&(_, hi)| hi <= start);
+                                shared[first..]
+                                    .iter()
+                                    .take_while(|&&(lo, _)| lo < end)
+                                    .cloned()
+                                    .collect::<Vec<_>>()
+                            }
+                        };
+                        let shared = shared
+                            .iter()
+                            .map(|&(lo, hi)| (lo.saturating_sub(start), hi.saturating_sub(start)));
+                        line.kind = if kind == LineKind::Added {
+                            "added"
+                        } else {
+                            "removed"
+                        };
+                        line.segments = Some(json::segments(line.text, shared));
+                        line.moved = moved.is_some();
+                    }
+                    Some(LineKind::Context) => (),
+                    _ => {
+                        // the warnings, and the separators of context
+                        // and normal diffs
+                        line.text = &data[range.0..end];
+                        line.kind = if line.text.starts_with(b"\\") {
+                            "no-newline"
+                        } else {
+                            "separator"
+                        };
+                    }
                 }
+                json_lines.push(line);
             }
-            let first = data[range.0];
-            match first {
-                b'-' | b'+' => {
-                    let is_plus = first == b'+';
-                    let (nhl, hl, toks, shared) = if is_plus {
-                        (
-                            &config.added_face,
-                            &config.refine_added_face,
-                            tokens.added(),
-                            &mut shared_added,
-                        )
+            json::write_hunk(out, header_line, line_number_info.as_ref(), &json_lines)?;
+        } else if side_by_side {
+            // the changed lines between two context lines are laid out
+            // together, and the warnings are written after them
+            let width = config.width.unwrap_or_else(terminal::width);
+            let lino_face = config.html.map(|_| &config.line_number_face);
+            let mut layout = SideBySide::new(
+                line_number_info.as_ref(),
+                width,
+                prefix_len,
+                config.tab_stop(),
+                lino_face,
+            );
+            let (mut old, mut new, mut pending) = (vec![], vec![], vec![]);
+            let (mut nb_removed, mut nb_added) = (0, 0);
+            for (i, range) in lines.iter().enumerate() {
+                let kind = hunk_kinds[i];
+                if let Some(kind @ LineKind::Removed) | Some(kind @ LineKind::Added) = kind {
+                    let mut line = StyledLine::default();
+                    paint_changed(i, range, kind == LineKind::Added, &mut line)?;
+                    if kind == LineKind::Added {
+                        new.push(line);
                     } else {
-                        (
-                            &config.removed_face,
-                            &config.refine_removed_face,
-                            tokens.removed(),
-                            &mut shared_removed,
-                        )
-                    };
-                    if config.has_line_numbers() {
-                        margin.write_margin_changed(is_plus, config, out)?
+                        old.push(line);
+                    }
+                    if i + 1 < lines.nb_lines() {
+                        continue;
                     }
-                    Self::paint_line(toks.data(), &range, nhl, hl, shared, out)?;
+                } else if kind != Some(LineKind::Context) {
+                    // the columns already separate the two sides of
+                    // the commands of normal diffs
+                    if format != HunkFormat::Normal || !data[range.0..].starts_with(b"---") {
+                        pending.push(range);
+                    }
+                    if i + 1 < lines.nb_lines() {
+                        continue;
+                    }
+                }
+                let pairs = refinement
+                    .pairs
+                    .iter()
+                    .filter(|&&(i, j)| nb_removed <= i && nb_added <= j)
+                    .map(|&(i, j)| (i - nb_removed, j - nb_added))
+                    .filter(|&(i, j)| i < old.len() && j < new.len())
+                    .collect::<Vec<_>>();
+                side_by_side::write_block(&mut layout, &old, &new, &pairs, out)?;
+                nb_removed += old.len();
+                nb_added += new.len();
+                old.clear();
+                new.clear();
+                for range in pending.drain(..) {
+                    output(data, range.0, range.1, &defaultspec, out)?;
                 }
-                _ => {
-                    if config.has_line_numbers() {
-                        margin.write_margin_context(config, out)?
+                if kind == Some(LineKind::Context) {
+                    let mut line = StyledLine::default();
+                    output(data, range.0, range.1, &defaultspec, &mut line)?;
+                    layout.write_row(Some(&line), Some(&line), out)?;
+                }
+            }
+        } else {
+            // expanded tabs are laid out like wrapped lines, on a single
+            // row
+            let wrap_width = if config.wrap {
+                Some(config.width.unwrap_or_else(terminal::width))
+            } else {
+                config.tab_width.map(|_| usize::MAX)
+            };
+            let margin_width = if config.has_line_numbers() {
+                margin.width(config)
+            } else {
+                0
+            };
+            for (i, range) in lines.iter().enumerate() {
+                if let Some(&&nline) = warnings.peek() {
+                    if nline == i {
+                        let w = &lines.data()[range.0..range.1];
+                        output(w, 0, w.len(), &defaultspec, out)?;
+                        warnings.next();
+                        continue;
+                    }
+                }
+                let prefix = prefix(&range);
+                match hunk_kinds[i] {
+                    Some(kind @ LineKind::Removed) | Some(kind @ LineKind::Added) => {
+                        let is_plus = kind == LineKind::Added;
+                        let nhl = if is_plus {
+                            &config.added_face
+                        } else {
+                            &config.removed_face
+                        };
+                        if config.has_line_numbers() {
+                            match format {
+                                HunkFormat::Unified => {
+                                    margin.write_margin_changed(prefix, config, out)?
+                                }
+                                HunkFormat::Context { .. } | HunkFormat::Normal => {
+                                    margin.write_margin_section(is_plus, nhl, config, out)?
+                                }
+                            }
+                        }
+                        match wrap_width {
+                            Some(width) => {
+                                let mut line = StyledLine::default();
+                                paint_changed(i, range, is_plus, &mut line)?;
+                                let glyph = &config.wrap_glyph;
+                                wrap::write_wrapped(
+                                    &line,
+                                    prefix_len,
+                                    config.tab_stop(),
+                                    margin_width,
+                                    glyph,
+                                    width,
+                                    out,
+                                )?;
+                            }
+                            None => paint_changed(i, range, is_plus, out)?,
+                        }
+                    }
+                    _ => {
+                        if config.has_line_numbers() {
+                            match format {
+                                HunkFormat::Unified => margin.write_margin_context(config, out)?,
+                                HunkFormat::Context { .. } | HunkFormat::Normal => margin
+                                    .write_margin_section(
+                                        in_new_section(i),
+                                        &defaultspec,
+                                        config,
+                                        out,
+                                    )?,
+                            }
+                        }
+                        match wrap_width {
+                            Some(width) => {
+                                let mut line = StyledLine::default();
+                                output(data, range.0, range.1, &defaultspec, &mut line)?;
+                                let glyph = &config.wrap_glyph;
+                                wrap::write_wrapped(
+                                    &line,
+                                    prefix_len,
+                                    config.tab_stop(),
+                                    margin_width,
+                                    glyph,
+                                    width,
+                                    out,
+                                )?;
+                            }
+                            None => output(data, range.0, range.1, &defaultspec, out)?,
+                        }
                     }
-                    output(data, range.0, range.1, &defaultspec, out)?
                 }
             }
+            assert!(warnings.peek().is_none());
         }
-        assert!(warnings.peek() == None);
-        drop(shared_removed);
-        drop(shared_added);
         lines.clear();
         added_tokens.clear();
         removed_tokens.clear();
+        added_lines.clear();
+        removed_lines.clear();
         warning_lines.clear();
         Ok(())
     }
@@ -611,8 +1685,9 @@ impl<'a> HunkBuffer<'a> {
     }
 
     fn push_aux(&mut self, line: &[u8], added: bool) {
+        let index = self.hunk_start + self.lines.nb_lines();
         // XXX: skip leading token
-        let mut ofs = self.lines.len() + 1;
+        let mut ofs = self.lines.len() + self.nb_parents;
         add_raw_line(&mut self.lines, line);
         // get back the line sanitized from escape codes:
         let line = &self.lines.data()[ofs..];
@@ -621,51 +1696,220 @@ impl<'a> HunkBuffer<'a> {
             .iter()
             .take_while(|ch| ch.is_ascii_whitespace())
             .count();
-        diffr_lib::tokenize(
-            &self.lines.data(),
-            ofs,
-            if added {
-                &mut self.added_tokens
-            } else {
-                &mut self.removed_tokens
-            },
+        let (tokens, lines) = if added {
+            (&mut self.added_tokens, &mut self.added_lines)
+        } else {
+            (&mut self.removed_tokens, &mut self.removed_lines)
+        };
+        let first_token = tokens.len();
+        if self.moved_lines.contains_key(&index) {
+            // moved lines are refined against their counterpart only
+            lines.push((first_token, first_token));
+            return;
+        }
+        self.tokenizer.tokenize(self.lines.data(), ofs, tokens);
+        if let Some(ignore_whitespace) = self.config.ignore_whitespace {
+            ignore_whitespace.filter(self.lines.data(), tokens, first_token);
+        }
+        lines.push((first_token, tokens.len()));
+    }
+
+    /// The kind of a line of a hunk of a context diff, or None if it
+    /// ends the hunk. The header of the new section, the line `index` of
+    /// the hunk, is kept as is like the warnings of missing newlines.
+    fn context_line_kind(
+        &mut self,
+        line: &[u8],
+        new_section: Option<usize>,
+        index: usize,
+    ) -> Option<LineKind> {
+        let line = strip_escape_codes(line);
+        if new_section.is_none() {
+            if let Some(range) = parse_context_range(&line, b"---", b"----") {
+                self.format = HunkFormat::Context {
+                    new_section: Some(index),
+                };
+                if let Some(header) = &mut self.line_number_info {
+                    header.plus_range = range;
+                }
+                return Some(LineKind::Absent);
+            }
+        }
+        context_line_kind(*line.first()?, new_section.is_some())
+    }
+
+    /// Find the moved blocks of `input`, and refine each of their lines
+    /// against its counterpart.
+    fn find_moved_lines(&mut self, input: &[u8]) -> MovedLines {
+        struct ChangedLine<'b> {
+            index: usize,
+            block: usize,
+            raw: &'b [u8],
+            content: (usize, usize),
+        }
+
+        let mut sanitized = LineSplit::default();
+        let mut removed = vec![];
+        let mut added = vec![];
+        let mut in_hunk = false;
+        let mut block = 0;
+        let mut nb_parents = 1;
+        for (index, raw) in input.split_inclusive(|&b| b == b'\n').enumerate() {
+            if in_hunk {
+                match hunk_line_kind(raw, nb_parents) {
+                    // only the diffs of single files are searched
+                    Some(kind @ LineKind::Added) | Some(kind @ LineKind::Removed)
+                        if nb_parents == 1 =>
+                    {
+                        let lo = sanitized.len();
+                        add_raw_line(&mut sanitized, raw);
+                        let lines = if kind == LineKind::Added {
+                            &mut added
+                        } else {
+                            &mut removed
+                        };
+                        lines.push(ChangedLine {
+                            index,
+                            block,
+                            raw,
+                            content: (lo + 1, sanitized.len()),
+                        });
+                    }
+                    Some(LineKind::Context) => block += 1,
+                    Some(_) => (),
+                    None => in_hunk = false,
+                }
+            }
+            if !in_hunk {
+                block += 1;
+                in_hunk = first_after_escape(raw) == Some(b'@');
+                if in_hunk {
+                    nb_parents = parse_line_number(raw).map_or(1, |header| header.nb_parents());
+                }
+            }
+        }
+
+        let diff_lines = |lines: &[ChangedLine]| {
+            lines
+                .iter()
+                .map(|line| DiffLine {
+                    index: line.index,
+                    block: line.block,
+                    content: &sanitized.data()[line.content.0..line.content.1],
+                })
+                .collect::<Vec<_>>()
+        };
+        let blocks = find_moved_blocks(&diff_lines(&removed), &diff_lines(&added));
+        let mut moved_lines = HashMap::new();
+        for block in blocks {
+            for k in 0..block.len {
+                let removed = &removed[block.removed + k];
+                let added = &added[block.added + k];
+                self.refine_moved_pair(removed.raw, added.raw);
+                moved_lines.insert(removed.index, self.refinement.shared_removed.clone());
+                moved_lines.insert(added.index, self.refinement.shared_added.clone());
+            }
+        }
+        moved_lines
+    }
+
+    /// Refine a moved line against its counterpart: the segments they
+    /// share, relative to the start of each line, are left in
+    /// `self.refinement`.
+    fn refine_moved_pair(&mut self, removed: &[u8], added: &[u8]) {
+        self.push_removed(removed);
+        let added_start = self.lines.len();
+        self.push_added(added);
+        let data = self.lines.data();
+        let m = TokenMap::new(
+            &mut [
+                (self.removed_tokens.iter(), data),
+                (self.added_tokens.iter(), data),
+            ],
+            self.config.token_normalization(),
+        );
+        self.refinement.clear();
+        self.refinement.refine(
+            data,
+            &m,
+            &self.removed_tokens,
+            &self.added_tokens,
+            &mut self.stats,
         );
+        for segment in &mut self.refinement.shared_added {
+            segment.0 -= added_start;
+            segment.1 -= added_start;
+        }
+        self.lines.clear();
+        self.added_tokens.clear();
+        self.removed_tokens.clear();
+        self.added_lines.clear();
+        self.removed_lines.clear();
     }
 
-    fn run(&mut self) -> io::Result<()> {
-        let stdin = io::stdin();
-        let stdout = StandardStream::stdout(ColorChoice::Always);
+    fn run(&mut self, input: impl BufRead) -> io::Result<()> {
+        let json = self.config.format == OutputFormat::Json;
+        let stdout = StandardStream::stdout(if self.config.color && !json {
+            ColorChoice::Always
+        } else {
+            ColorChoice::Never
+        });
         let mut buffer = vec![];
-        let mut stdin = stdin.lock();
-        let mut stdout = stdout.lock();
-        let mut stdout: Box<dyn WriteColor> = if self.config.html {
-            write!(stdout, "<pre>")?;
-            Box::new(HtmlColorWriter::new(stdout))
+        let mut data = vec![];
+        let mut input: Box<dyn BufRead> = if self.config.detect_moved {
+            // moved blocks can be anywhere: read the whole diff first
+            let mut input = input;
+            input.read_to_end(&mut data)?;
+            self.moved_lines = self.find_moved_lines(&data);
+            Box::new(&data[..])
         } else {
-            Box::new(stdout)
+            Box::new(input)
+        };
+        let stdout = stdout.lock();
+        let mut stdout: Box<dyn Output> = match self.config.html {
+            Some(mode) if !json => Box::new(HtmlColorWriter::new(stdout, self.config, mode)?),
+            _ => Box::new(stdout),
         };
+        // whether the start of the diff of the current file was marked
+        let mut in_file = false;
         let mut in_hunk = false;
         let mut hunk_line_number = 0;
+        let mut line_index = 0;
+        let mut file_headers = match self.config.file_header_style {
+            FileHeaderStyle::Raw if !json => None,
+            FileHeaderStyle::Raw | FileHeaderStyle::Banner => Some(FileHeaderParser::default()),
+        };
 
         // process hunks
         loop {
-            stdin.read_until(b'\n', &mut buffer)?;
+            input.read_until(b'\n', &mut buffer)?;
             if buffer.is_empty() {
                 break;
             }
+            if !self.config.color && self.config.html.is_none() {
+                // the colors of the input are removed too
+                buffer = strip_escape_codes(&buffer);
+            }
+            line_index += 1;
 
-            let first = first_after_escape(&buffer);
             if in_hunk {
                 hunk_line_number += 1;
-                match first {
-                    Some(b'+') => self.push_added(&buffer),
-                    Some(b'-') => self.push_removed(&buffer),
-                    Some(b' ') => add_raw_line(&mut self.lines, &buffer),
-                    Some(b'\\') => {
+                let kind = match self.format {
+                    HunkFormat::Unified => hunk_line_kind(&buffer, self.nb_parents),
+                    HunkFormat::Context { new_section } => {
+                        self.context_line_kind(&buffer, new_section, hunk_line_number - 1)
+                    }
+                    HunkFormat::Normal => normal_line_kind(&strip_escape_codes(&buffer)),
+                };
+                match kind {
+                    Some(LineKind::Added) => self.push_added(&buffer),
+                    Some(LineKind::Removed) => self.push_removed(&buffer),
+                    Some(LineKind::Context) => add_raw_line(&mut self.lines, &buffer),
+                    Some(LineKind::Absent) => {
                         add_raw_line(&mut self.lines, &buffer);
                         self.warning_lines.push(hunk_line_number - 1);
                     }
-                    _ => {
+                    None => {
                         self.process_with_stats(&mut stdout)?;
                         in_hunk = false;
                     }
@@ -673,11 +1917,55 @@ impl<'a> HunkBuffer<'a> {
             }
             if !in_hunk {
                 hunk_line_number = 0;
-                in_hunk = first == Some(b'@');
-                if self.config.has_line_numbers() && in_hunk {
-                    self.line_number_info = parse_line_number(&buffer);
+                self.hunk_start = line_index;
+                in_hunk = first_after_escape(&buffer) == Some(b'@');
+                if in_hunk {
+                    let header = parse_line_number(&buffer);
+                    self.nb_parents = header.as_ref().map_or(1, HunkHeader::nb_parents);
+                    self.format = HunkFormat::Unified;
+                    if self.config.has_line_numbers() || self.config.side_by_side || json {
+                        self.line_number_info = header;
+                    }
+                } else {
+                    let sanitized = strip_escape_codes(&buffer);
+                    let (format, header) = match parse_context_range(&sanitized, b"***", b"****") {
+                        Some(range) => (
+                            HunkFormat::Context { new_section: None },
+                            Some(HunkHeader::new(vec![range], (0, 0))),
+                        ),
+                        None => (HunkFormat::Normal, parse_normal_command(&sanitized)),
+                    };
+                    if format != HunkFormat::Normal || header.is_some() {
+                        in_hunk = true;
+                        self.nb_parents = 1;
+                        self.format = format;
+                        if self.config.has_line_numbers() || self.config.side_by_side || json {
+                            self.line_number_info = header;
+                        }
+                    }
+                }
+                if in_hunk {
+                    in_file = false;
+                } else if file_headers.is_none() && starts_file(&buffer, in_file) {
+                    stdout.start_file()?;
+                    in_file = true;
+                }
+                let in_file_header = match &mut file_headers {
+                    Some(parser) => {
+                        parser.push(&buffer) || {
+                            parser.flush(self.config, &mut stdout)?;
+                            parser.push(&buffer)
+                        }
+                    }
+                    None => false,
+                };
+                if json && in_hunk {
+                    self.header_line.clone_from(&buffer);
+                } else if json && !in_file_header {
+                    json::write_text(&mut stdout, &buffer)?;
+                } else if !in_file_header {
+                    output(&buffer, 0, buffer.len(), &ColorSpec::default(), &mut stdout)?;
                 }
-                output(&buffer, 0, buffer.len(), &ColorSpec::default(), &mut stdout)?;
             }
 
             buffer.clear();
@@ -685,12 +1973,32 @@ impl<'a> HunkBuffer<'a> {
 
         // flush remaining hunk
         self.process_with_stats(&mut stdout)?;
+        if let Some(parser) = &mut file_headers {
+            parser.flush(self.config, &mut stdout)?;
+        }
+        stdout.finish()?;
         self.stats.stop();
         self.stats.report()?;
         Ok(())
     }
 }
 
+/// Whether `line`, outside of the hunks, starts the diff of a file,
+/// `in_file` telling if the start of the diff of a file was already
+/// found since the last hunk.
+fn starts_file(line: &[u8], in_file: bool) -> bool {
+    let line = strip_escape_codes(line);
+    let headers: [&[u8]; 3] = [b"--- ", b"*** ", b"Index: "];
+    line.starts_with(b"diff ") || !in_file && headers.iter().any(|h| line.starts_with(h))
+}
+
+/// `line` without its escape codes.
+fn strip_escape_codes(line: &[u8]) -> Vec<u8> {
+    let mut dst = LineSplit::default();
+    add_raw_line(&mut dst, line);
+    dst.data().to_vec()
+}
+
 // TODO count whitespace characters as well here
 fn add_raw_line(dst: &mut LineSplit, line: &[u8]) {
     let mut i = 0;
@@ -733,6 +2041,31 @@ where
     Ok(())
 }
 
+/// Output `buf[from..to]` like `output`, between the `markers` if any,
+/// written with the same face.
+fn output_marked<Stream>(
+    buf: &[u8],
+    from: usize,
+    to: usize,
+    colorspec: &ColorSpec,
+    markers: Option<&(String, String)>,
+    out: &mut Stream,
+) -> io::Result<()>
+where
+    Stream: WriteColor,
+{
+    let (open, close) = match markers {
+        Some(markers) if from < to.min(buf.len()) => markers,
+        _ => return output(buf, from, to, colorspec, out),
+    };
+    let to = to.min(buf.len());
+    // the newline stays after the closing marker
+    let end = if buf[to - 1] == b'\n' { to - 1 } else { to };
+    let marked = [open.as_bytes(), &buf[from..end], close.as_bytes()].concat();
+    output(&marked, 0, marked.len(), colorspec, out)?;
+    out.write_all(&buf[end..to])
+}
+
 /// Returns the number of bytes of escape code that start the slice.
 fn skip_all_escape_code(buf: &[u8]) -> usize {
     // Skip one sequence
@@ -792,11 +2125,11 @@ fn skip_token(buf: &[u8]) -> usize {
     }
 }
 
-// TODO: extend to the multiple range case
 #[derive(Default, PartialEq, Eq)]
 struct HunkHeader {
     // range are (ofs,len) for the interval [ofs, ofs + len)
-    minus_range: (usize, usize),
+    // one minus range per parent: combined diffs of merges have several
+    minus_ranges: Vec<(usize, usize)>,
     plus_range: (usize, usize),
 }
 
@@ -831,26 +2164,38 @@ fn width1(x: u64) -> usize {
 }
 
 impl HunkHeader {
-    fn new(minus_range: (usize, usize), plus_range: (usize, usize)) -> Self {
+    fn new(minus_ranges: Vec<(usize, usize)>, plus_range: (usize, usize)) -> Self {
         HunkHeader {
-            minus_range,
+            minus_ranges,
             plus_range,
         }
     }
 
+    fn nb_parents(&self) -> usize {
+        self.minus_ranges.len()
+    }
+
+    /// The width of the column of the line numbers of one file.
+    fn column_width(&self) -> usize {
+        self.minus_ranges
+            .iter()
+            .chain(Some(&self.plus_range))
+            .map(|range| width1((range.0 + range.1) as u64))
+            .max()
+            .unwrap_or(0)
+    }
+
     fn width(&self) -> usize {
-        2 * width1((self.minus_range.0 + self.minus_range.1) as u64)
-            .max(width1((self.plus_range.0 + self.plus_range.1) as u64))
-            + 1
+        (self.nb_parents() + 1) * self.column_width() + self.nb_parents()
     }
 }
 
 impl Debug for HunkHeader {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), FmtErr> {
-        f.write_fmt(format_args!(
-            "-{},{} +{},{}",
-            self.minus_range.0, self.minus_range.1, self.plus_range.0, self.plus_range.1,
-        ))
+        for range in &self.minus_ranges {
+            f.write_fmt(format_args!("-{},{} ", range.0, range.1))?;
+        }
+        f.write_fmt(format_args!("+{},{}", self.plus_range.0, self.plus_range.1))
     }
 }
 
@@ -950,34 +2295,38 @@ impl<'a> LineNumberParser<'a> {
         Some((p0, p1))
     }
 
-    fn expect_multiple_minus_ranges(&mut self) -> Option<(usize, usize)> {
+    fn expect_multiple_minus_ranges(&mut self) -> Option<Vec<(usize, usize)>> {
         let next = |that: &mut Self| {
             that.expect(b'-')?;
             that.parse_pair()
         };
-        let mut res = None;
+        let mut res = vec![];
         for i in 0.. {
             if i != 0 {
                 self.expect_multiple(|x| x.is_ascii_whitespace())?;
             }
             match next(self) {
-                next @ Some(_) => res = next,
+                Some(range) => res.push(range),
                 None => break,
             }
         }
-        res
+        if res.is_empty() {
+            None
+        } else {
+            Some(res)
+        }
     }
 
     fn parse_line_number(&mut self) -> Option<HunkHeader> {
         self.skip_whitespaces();
         self.expect_multiple(|x| x == b'@')?;
         self.expect_multiple(|x| x.is_ascii_whitespace())?;
-        let minus_range = self.expect_multiple_minus_ranges()?;
+        let minus_ranges = self.expect_multiple_minus_ranges()?;
         self.expect(b'+')?;
         let plus_range = self.parse_pair()?;
         self.expect_multiple(|x| x.is_ascii_whitespace())?;
         self.expect_multiple(|x| x == b'@')?;
-        Some(HunkHeader::new(minus_range, plus_range))
+        Some(HunkHeader::new(minus_ranges, plus_range))
     }
 }
 
@@ -0,0 +1,177 @@
+//! Layout of `--side-by-side`: the old version of the lines of a hunk
+//! in a left column, and the new version in a right column.
+
+use std::io;
+use termcolor::{ColorSpec, WriteColor};
+
+use super::wrap::{wrap, Cell, StyledLine};
+use super::{output, HunkHeader};
+
+/// The separator between the two columns.
+const SEPARATOR: &str = " │ ";
+
+/// The rows of one side of a row, with the line number and the face
+/// of the start of its line.
+struct Side<'a> {
+    lino: Option<usize>,
+    face: Option<ColorSpec>,
+    cells: &'a [Cell],
+}
+
+/// The state of the layout of a hunk: the width of the columns, and
+/// the line numbers of the next lines of each side.
+pub struct SideBySide {
+    text_width: usize,
+    lino_width: usize,
+    lino_old: usize,
+    lino_new: usize,
+    /// The face of the line numbers, instead of the face of the start of
+    /// their line.
+    lino_face: Option<ColorSpec>,
+    prefix_len: usize,
+    tab_stop: usize,
+}
+
+impl SideBySide {
+    /// The layout of a hunk with `header`, filling `width` characters.
+    /// Lines are numbered only if the header is known, and their tabs
+    /// are expanded to the tab stops every `tab_stop` characters.
+    pub fn new(
+        header: Option<&HunkHeader>,
+        width: usize,
+        prefix_len: usize,
+        tab_stop: usize,
+        lino_face: Option<&ColorSpec>,
+    ) -> Self {
+        let (lino_width, lino_old, lino_new) = match header {
+            Some(header) => (
+                header.column_width(),
+                header.minus_ranges.first().map_or(0, |range| range.0),
+                header.plus_range.0,
+            ),
+            None => (0, 0, 0),
+        };
+        let column_width = width.saturating_sub(SEPARATOR.chars().count()) / 2;
+        let lino_margin = if lino_width == 0 { 0 } else { lino_width + 1 };
+        SideBySide {
+            text_width: column_width.saturating_sub(lino_margin).max(1),
+            lino_width,
+            lino_old,
+            lino_new,
+            lino_face: lino_face.cloned(),
+            prefix_len,
+            tab_stop,
+        }
+    }
+
+    /// Write a row with the line `old` on the left and `new` on the
+    /// right, on several lines if one of them is too long.
+    pub fn write_row(
+        &mut self,
+        old: Option<&StyledLine>,
+        new: Option<&StyledLine>,
+        out: &mut impl WriteColor,
+    ) -> io::Result<()> {
+        let wrap = |line: Option<&StyledLine>| {
+            line.map_or(vec![], |line| {
+                let (prefix_len, tab_stop) = (self.prefix_len, self.tab_stop);
+                wrap(line, prefix_len, tab_stop, self.text_width, self.text_width)
+            })
+        };
+        let (old_cells, new_cells) = (wrap(old), wrap(new));
+        let old_side = Side {
+            lino: old.map(|_| self.lino_old),
+            face: old.map(StyledLine::first_face),
+            cells: &old_cells,
+        };
+        let new_side = Side {
+            lino: new.map(|_| self.lino_new),
+            face: new.map(StyledLine::first_face),
+            cells: &new_cells,
+        };
+        for k in 0..old_cells.len().max(new_cells.len()) {
+            self.write_cell(&old_side, k, true, out)?;
+            // no trailing whitespace when the right column is empty
+            let separator = if k < new_cells.len() {
+                SEPARATOR
+            } else {
+                SEPARATOR.trim_end()
+            };
+            out.write_all(separator.as_bytes())?;
+            self.write_cell(&new_side, k, false, out)?;
+            out.write_all(b"\n")?;
+        }
+        if old.is_some() {
+            self.lino_old += 1;
+        }
+        if new.is_some() {
+            self.lino_new += 1;
+        }
+        Ok(())
+    }
+
+    /// Write the `k`-th row of a side, padded with spaces on the left
+    /// column.
+    fn write_cell(
+        &self,
+        side: &Side,
+        k: usize,
+        pad: bool,
+        out: &mut impl WriteColor,
+    ) -> io::Result<()> {
+        let cell = side.cells.get(k);
+        if self.lino_width != 0 {
+            let face = match (&self.lino_face, &side.face) {
+                (Some(face), _) | (None, Some(face)) => face.clone(),
+                (None, None) => ColorSpec::default(),
+            };
+            let lino = match side.lino {
+                Some(lino) if k == 0 => format!("{:w$} ", lino, w = self.lino_width),
+                _ => format!("{:w$} ", "", w = self.lino_width),
+            };
+            if pad || cell.is_some() {
+                output(lino.as_bytes(), 0, lino.len(), &face, out)?;
+            }
+        }
+        let len = match cell {
+            Some(cell) => {
+                cell.write(out)?;
+                cell.len
+            }
+            None => 0,
+        };
+        if pad {
+            write!(out, "{:w$}", "", w = self.text_width.saturating_sub(len))?;
+        }
+        Ok(())
+    }
+}
+
+/// Lay out the removed lines `old` and the added lines `new` of a
+/// block of changed lines, side by side. `pairs` are the indexes of the
+/// lines paired with each other, in increasing order: the lines between
+/// two pairs are put side by side in order.
+pub fn write_block(
+    layout: &mut SideBySide,
+    old: &[StyledLine],
+    new: &[StyledLine],
+    pairs: &[(usize, usize)],
+    out: &mut impl WriteColor,
+) -> io::Result<()> {
+    let (mut i, mut j) = (0, 0);
+    for &(pair_i, pair_j) in pairs.iter().chain(Some(&(old.len(), new.len()))) {
+        while i < pair_i || j < pair_j {
+            let old_line = if i < pair_i { old.get(i) } else { None };
+            let new_line = if j < pair_j { new.get(j) } else { None };
+            layout.write_row(old_line, new_line, out)?;
+            i += old_line.is_some() as usize;
+            j += new_line.is_some() as usize;
+        }
+        if pair_i < old.len() && pair_j < new.len() {
+            layout.write_row(old.get(pair_i), new.get(pair_j), out)?;
+            i = pair_i + 1;
+            j = pair_j + 1;
+        }
+    }
+    Ok(())
+}
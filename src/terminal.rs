@@ -0,0 +1,60 @@
+//! Size and capabilities of the terminal diffr writes to.
+
+use std::env;
+
+/// The width of the output when it cannot be found.
+pub const DEFAULT_WIDTH: usize = 80;
+
+/// The number of columns of the terminal.
+///
+/// `COLUMNS` is used first: git sets it before starting its pager,
+/// since the size of the terminal cannot be queried from a pipe.
+/// Otherwise, the terminal of the standard streams is queried, and
+/// `DEFAULT_WIDTH` is used if none is a terminal.
+pub fn width() -> usize {
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .filter(|&columns| columns != 0)
+        .or_else(query_width)
+        .unwrap_or(DEFAULT_WIDTH)
+}
+
+#[cfg(unix)]
+fn query_width() -> Option<usize> {
+    // the standard output is often a pipe to a pager, while the
+    // standard error is still the terminal
+    for &fd in &[libc::STDOUT_FILENO, libc::STDERR_FILENO, libc::STDIN_FILENO] {
+        let mut size = libc::winsize {
+            ws_row: 0,
+            ws_col: 0,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: TIOCGWINSZ only writes a winsize to its argument.
+        let result = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) };
+        if result == 0 && size.ws_col != 0 {
+            return Some(size.ws_col as usize);
+        }
+    }
+    None
+}
+
+#[cfg(not(unix))]
+fn query_width() -> Option<usize> {
+    None
+}
+
+/// Whether the environment lets the output be colored: not if
+/// `NO_COLOR` is set to a non-empty value (see https://no-color.org), or
+/// if the terminal is dumb.
+pub fn color_allowed() -> bool {
+    let no_color = matches!(env::var_os("NO_COLOR"), Some(value) if !value.is_empty());
+    let dumb = matches!(env::var_os("TERM"), Some(term) if term == "dumb");
+    !no_color && !dumb
+}
+
+/// Whether the standard output is a terminal.
+pub fn is_stdout_tty() -> bool {
+    atty::is(atty::Stream::Stdout)
+}
@@ -31,7 +31,7 @@ fn parse_line_number_test() {
         eprintln!("test_ok {}...", String::from_utf8_lossy(input));
         assert_eq!(
             Some(HunkHeader {
-                minus_range: (ofs1, len1),
+                minus_ranges: vec![(ofs1, len1)],
                 plus_range: (ofs2, len2),
             }),
             parse_line_number(input)
@@ -45,8 +45,15 @@ fn parse_line_number_test() {
     test_ok(0, 0, 1, 1, b"@@ -0,0 +1 @@");
     test_ok(0, 0, 1, 1, b"  @@ -0,0 +1 @@");
     test_ok(0, 0, 1, 1, b"@@   -0,0 +1 @@");
-    // last one wins
-    test_ok(0, 2, 0, 3, b"@@@ -0,0 -0,2 +0,3 @@@");
+    // combined diff
+    assert_eq!(
+        Some(HunkHeader::new(vec![(0, 0), (0, 2)], (0, 3))),
+        parse_line_number(b"@@@ -0,0 -0,2 +0,3 @@@")
+    );
+    assert_eq!(
+        Some(HunkHeader::new(vec![(1, 2), (3, 4), (5, 6)], (7, 8))),
+        parse_line_number(b"@@@@ -1,2 -3,4 -5,6 +7,8 @@@@ fn main()")
+    );
     test_fail(b"@@-0,0 +1 @@");
     test_fail(b"@@ -0,0+1 @@");
     test_fail(b"@@ -0,0 +1@@");
@@ -85,11 +92,732 @@ fn test_width() {
     }
     test(u64::max_value());
 
-    assert_eq!("123:456".len(), HunkHeader::new((123, 5), (456, 9)).width());
+    assert_eq!(
+        "123:456".len(),
+        HunkHeader::new(vec![(123, 5)], (456, 9)).width()
+    );
     assert_eq!(
         "1122: 456".len(),
-        HunkHeader::new((123, 999), (456, 9)).width()
+        HunkHeader::new(vec![(123, 999)], (456, 9)).width()
+    );
+    assert_eq!(
+        "   :456".len(),
+        HunkHeader::new(vec![(0, 0)], (456, 9)).width()
+    );
+    assert_eq!(
+        " 12 123 456".len(),
+        HunkHeader::new(vec![(12, 5), (123, 5)], (456, 9)).width()
     );
-    assert_eq!("   :456".len(), HunkHeader::new((0, 0), (456, 9)).width());
     assert_eq!(MAX_MARGIN, 2 * width1(u64::max_value()) + 1);
 }
+
+#[test]
+fn change_blocks_test() {
+    let mut lines = LineSplit::default();
+    for line in &[
+        " context\n",
+        "-a\n",
+        "-b\n",
+        "+c\n",
+        " context\n",
+        "+d\n",
+        "\\ No newline at end of file\n",
+        "+e\n",
+        " context\n",
+        "-f\n",
+    ] {
+        lines.append_line(line.as_bytes());
+    }
+    let kinds = lines
+        .iter()
+        .map(|range| line_kind(&lines.data()[range.0..range.0 + 1], 0))
+        .collect::<Vec<_>>();
+    let blocks = change_blocks(&kinds);
+    let blocks = blocks
+        .iter()
+        .map(|b| (b.removed, b.added))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![((0, 2), (0, 1)), ((2, 2), (1, 3)), ((2, 3), (3, 3))],
+        blocks
+    );
+}
+
+#[test]
+fn line_kind_test() {
+    use LineKind::*;
+    let kinds = |prefix: &[u8]| {
+        (0..prefix.len())
+            .map(|parent| line_kind(prefix, parent))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(vec![Removed], kinds(b"-"));
+    assert_eq!(vec![Added], kinds(b"+"));
+    assert_eq!(vec![Context], kinds(b" "));
+    assert_eq!(vec![Absent], kinds(b"\\"));
+    assert_eq!(vec![Added, Added], kinds(b"++"));
+    assert_eq!(vec![Context, Added], kinds(b" +"));
+    assert_eq!(vec![Removed, Removed], kinds(b"--"));
+    assert_eq!(vec![Absent, Removed], kinds(b" -"));
+    assert_eq!(vec![Context, Context], kinds(b"  "));
+
+    assert_eq!(Some(Added), prefix_kind(b" +"));
+    assert_eq!(Some(Removed), prefix_kind(b"- "));
+    assert_eq!(Some(Context), prefix_kind(b"  "));
+    assert_eq!(Some(Absent), prefix_kind(b"\\ "));
+    assert_eq!(None, prefix_kind(b"@@"));
+    assert_eq!(None, prefix_kind(b""));
+
+    assert_eq!(Some(Added), hunk_line_kind(b"\x1b[32m+\x1b[m+x\n", 2));
+    assert_eq!(Some(Context), hunk_line_kind(b"  x\n", 2));
+    assert_eq!(None, hunk_line_kind(b"+x\n", 2));
+    assert_eq!(Some(Added), hunk_line_kind(b"+x\n", 1));
+    assert_eq!(Some(Absent), hunk_line_kind(b"\\\n", 2));
+    assert_eq!(None, hunk_line_kind(b"\n", 2));
+    assert_eq!(None, hunk_line_kind(b"diff --cc a\n", 2));
+}
+
+#[test]
+fn combined_change_blocks_test() {
+    use LineKind::*;
+    let prefixes: [&[u8]; 6] = [b"  ", b"- ", b" -", b"++", b" +", b"  "];
+    let blocks = |parent| {
+        let kinds = prefixes
+            .iter()
+            .map(|prefix| line_kind(prefix, parent))
+            .collect::<Vec<_>>();
+        change_blocks(&kinds)
+            .iter()
+            .map(|b| (b.removed, b.added))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(vec![((0, 1), (0, 1))], blocks(0));
+    assert_eq!(vec![((0, 1), (0, 2))], blocks(1));
+
+    let hunk_kinds = prefixes.iter().map(|p| prefix_kind(p)).collect::<Vec<_>>();
+    let kinds = prefixes.iter().map(|p| line_kind(p, 0)).collect::<Vec<_>>();
+    let tokens = [(0, 1), (1, 2), (2, 3)];
+    let lines = [(0, 2), (2, 3)];
+    assert_eq!(
+        ChangedLines {
+            tokens: vec![(0, 1), (1, 2)],
+            lines: vec![(0, 2)],
+        },
+        parent_lines(&hunk_kinds, &kinds, Added, &tokens, &lines)
+    );
+}
+
+#[test]
+fn parse_context_range_test() {
+    assert_eq!(
+        Some((1, 6)),
+        parse_context_range(b"*** 1,6 ****\n", b"***", b"****")
+    );
+    assert_eq!(
+        Some((8, 5)),
+        parse_context_range(b"--- 8,12 ----\r\n", b"---", b"----")
+    );
+    assert_eq!(
+        Some((3, 1)),
+        parse_context_range(b"*** 3 ****", b"***", b"****")
+    );
+    assert_eq!(
+        Some((0, 0)),
+        parse_context_range(b"--- 0 ----", b"---", b"----")
+    );
+    assert_eq!(None, parse_context_range(b"*** 1,6 ****", b"---", b"----"));
+    assert_eq!(
+        None,
+        parse_context_range(b"*** a.txt\t2020-01-01", b"***", b"****")
+    );
+    assert_eq!(
+        None,
+        parse_context_range(b"***************", b"***", b"****")
+    );
+    assert_eq!(None, parse_context_range(b"*** 6,1 ****", b"***", b"****"));
+    assert_eq!(None, parse_context_range(b"*** +1 ****", b"***", b"****"));
+}
+
+#[test]
+fn parse_normal_command_test() {
+    assert_eq!(
+        Some(HunkHeader::new(vec![(5, 3)], (5, 2))),
+        parse_normal_command(b"5,7c5,6\n")
+    );
+    assert_eq!(
+        Some(HunkHeader::new(vec![(10, 0)], (11, 1))),
+        parse_normal_command(b"10a11\n")
+    );
+    assert_eq!(
+        Some(HunkHeader::new(vec![(0, 0)], (1, 3))),
+        parse_normal_command(b"0a1,3\n")
+    );
+    assert_eq!(
+        Some(HunkHeader::new(vec![(12, 2)], (11, 0))),
+        parse_normal_command(b"12,13d11\r\n")
+    );
+    assert_eq!(None, parse_normal_command(b"5,7c\n"));
+    assert_eq!(None, parse_normal_command(b"c5\n"));
+    assert_eq!(None, parse_normal_command(b"5x6\n"));
+    assert_eq!(None, parse_normal_command(b"5c6 and more\n"));
+    assert_eq!(None, parse_normal_command(b"abc\n"));
+
+    assert_eq!(Some(LineKind::Removed), normal_line_kind(b"< a\n"));
+    assert_eq!(Some(LineKind::Added), normal_line_kind(b"> a\n"));
+    assert_eq!(Some(LineKind::Absent), normal_line_kind(b"---\n"));
+    assert_eq!(None, normal_line_kind(b"--- a\n"));
+    assert_eq!(None, normal_line_kind(b"5c5\n"));
+}
+
+#[test]
+fn context_change_blocks_test() {
+    let mut lines = LineSplit::default();
+    let mut kinds = vec![];
+    let mut new_section = false;
+    for line in &[
+        "  context\n",
+        "! a\n",
+        "! b\n",
+        "  context\n",
+        "- c\n",
+        "  context\n",
+        "! d\n",
+        "--- 1,6 ----\n",
+        "  context\n",
+        "! e\n",
+        "  context\n",
+        "+ f\n",
+        "  context\n",
+        "! g\n",
+        "! h\n",
+    ] {
+        lines.append_line(line.as_bytes());
+        if line.starts_with("---") {
+            new_section = true;
+            kinds.push(Some(LineKind::Absent));
+        } else {
+            kinds.push(context_line_kind(line.as_bytes()[0], new_section));
+        }
+    }
+    let blocks = context_change_blocks(lines.data(), &lines, &kinds);
+    let blocks = blocks
+        .iter()
+        .map(|b| (b.removed, b.added))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            ((0, 2), (0, 1)),
+            ((3, 4), (2, 4)),
+            ((2, 3), (0, 0)),
+            ((0, 0), (1, 2)),
+        ],
+        blocks
+    );
+}
+
+#[test]
+fn combined_margin_test() {
+    let config = AppConfig::default();
+    let header = HunkHeader::new(vec![(1, 4), (1, 4)], (1, 4));
+    let mut buf = vec![0; MAX_MARGIN];
+    let mut margin = Margin::new(&header, &mut buf);
+    let mut out = termcolor::Buffer::no_color();
+    margin.write_margin_context(&config, &mut out).unwrap();
+    out.write_all(b"|\n").unwrap();
+    for prefix in &[&b"- "[..], b" -", b"++", b" +", b"+ "] {
+        margin
+            .write_margin_changed(prefix, &config, &mut out)
+            .unwrap();
+        out.write_all(b"|\n").unwrap();
+    }
+    margin.write_margin_context(&config, &mut out).unwrap();
+    out.write_all(b"|\n").unwrap();
+    assert_eq!(
+        "    1|\n2    |\n  2  |\n    2|\n3   3|\n  3 4|\n4 4 5|\n",
+        String::from_utf8_lossy(out.as_slice())
+    );
+}
+
+#[test]
+fn wrap_test() {
+    let mut line = wrap::StyledLine::default();
+    let face = color_spec(Some(Color::Red), None, false);
+    output(b"-\tab\xc3\xa9", 0, 6, &face, &mut line).unwrap();
+    output(b"cd\n", 0, 3, &ColorSpec::default(), &mut line).unwrap();
+    let cells = wrap::wrap(&line, 1, 8, 6, 6);
+    let texts = cells
+        .iter()
+        .map(|cell| {
+            let text = cell
+                .segments
+                .iter()
+                .map(|(text, _)| String::from_utf8_lossy(text))
+                .collect::<Vec<_>>();
+            (text.join("|"), cell.len)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            ("-     ".to_owned(), 6),
+            ("   ".to_owned(), 3),
+            ("abé|cd".to_owned(), 5)
+        ],
+        texts
+    );
+    assert_eq!(face, cells[1].segments[0].1);
+    assert_eq!(ColorSpec::default(), cells[2].segments[1].1);
+}
+
+#[test]
+fn wrap_display_width_test() {
+    // wide characters take two columns, combining marks none
+    let mut line = wrap::StyledLine::default();
+    let text = "+日本語 e\u{301}te\u{301}\n";
+    output(
+        text.as_bytes(),
+        0,
+        text.len(),
+        &ColorSpec::default(),
+        &mut line,
+    )
+    .unwrap();
+    let cells = wrap::wrap(&line, 1, 8, 6, 6)
+        .into_iter()
+        .map(|cell| {
+            (
+                String::from_utf8_lossy(&cell.segments[0].0).into_owned(),
+                cell.len,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            ("+".to_owned(), 1),
+            ("日本語".to_owned(), 6),
+            (" e\u{301}te\u{301}".to_owned(), 4)
+        ],
+        cells
+    );
+    // a wide character does not fit in a row of one column
+    assert_eq!(2, wrap::wrap(&line, 1, 8, 1, 1)[1].len);
+}
+
+#[test]
+fn write_wrapped_test() {
+    let mut line = wrap::StyledLine::default();
+    let face = color_spec(Some(Color::Green), None, false);
+    output(b"+let x = foo(bar);\n", 0, 19, &face, &mut line).unwrap();
+    let mut out = termcolor::Buffer::no_color();
+    out.write_all(b"12 ").unwrap();
+    wrap::write_wrapped(&line, 1, 8, 3, ">", 13, &mut out).unwrap();
+    assert_eq!(
+        "12 +let x = \n   >foo(bar);\n",
+        String::from_utf8_lossy(out.as_slice())
+    );
+}
+
+#[test]
+fn expand_tabs_test() {
+    let mut line = wrap::StyledLine::default();
+    output(b"+\tif x {\n", 0, 9, &ColorSpec::default(), &mut line).unwrap();
+    let mut out = termcolor::Buffer::no_color();
+    out.write_all(b"1 ").unwrap();
+    wrap::write_wrapped(&line, 1, 4, 2, ">", usize::MAX, &mut out).unwrap();
+    assert_eq!("1 +    if x {\n", String::from_utf8_lossy(out.as_slice()));
+
+    // the last line of the input may have no newline
+    let mut line = wrap::StyledLine::default();
+    output(b"+\ty", 0, 3, &ColorSpec::default(), &mut line).unwrap();
+    let mut out = termcolor::Buffer::no_color();
+    wrap::write_wrapped(&line, 1, 4, 0, ">", usize::MAX, &mut out).unwrap();
+    assert_eq!("+    y", String::from_utf8_lossy(out.as_slice()));
+}
+
+#[test]
+fn aligned_margin_tabs_test() {
+    let config = AppConfig {
+        line_numbers_style: Some(LineNumberStyle::Aligned),
+        tab_width: Some(4),
+        ..AppConfig::default()
+    };
+    let header = HunkHeader::new(vec![(1, 2)], (1, 2));
+    let mut buf = vec![0; MAX_MARGIN];
+    let mut margin = Margin::new(&header, &mut buf);
+    assert_eq!(4, margin.width(&config));
+    let mut out = termcolor::Buffer::no_color();
+    margin.write_margin_context(&config, &mut out).unwrap();
+    out.write_all(b"|\n").unwrap();
+    assert_eq!("  1 |\n", String::from_utf8_lossy(out.as_slice()));
+}
+
+#[test]
+fn output_marked_test() {
+    let markers = ("[-".to_owned(), "-]".to_owned());
+    let mut out = termcolor::Buffer::no_color();
+    let face = ColorSpec::default();
+    output_marked(b"-foo bar\n", 5, 9, &face, Some(&markers), &mut out).unwrap();
+    output_marked(b"-foo bar\n", 1, 5, &face, Some(&markers), &mut out).unwrap();
+    output_marked(b"-foo bar\n", 0, 1, &face, None, &mut out).unwrap();
+    output_marked(b"-foo bar\n", 9, 9, &face, Some(&markers), &mut out).unwrap();
+    assert_eq!(
+        "[-bar-]\n[-foo -]-",
+        String::from_utf8_lossy(out.as_slice())
+    );
+}
+
+#[test]
+fn json_segments_test() {
+    let text = b"  foo(bar, baz)";
+    let (refined, shared) = json::segments(text, vec![(0, 6), (9, 11)].into_iter());
+    assert_eq!(vec![(6, 9), (11, 15)], refined);
+    assert_eq!(vec![(2, 6), (9, 11)], shared);
+    let (refined, shared) = json::segments(b"   ", std::iter::empty());
+    assert!(refined.is_empty() && shared.is_empty());
+}
+
+#[test]
+fn json_hunk_test() {
+    let header = HunkHeader::new(vec![(3, 2)], (3, 1));
+    let lines = [
+        json::Line {
+            kind: "removed",
+            text: b"a \"b\"",
+            segments: Some((vec![(2, 5)], vec![(0, 2)])),
+            moved: false,
+        },
+        json::Line {
+            kind: "context",
+            text: b"\tc",
+            segments: None,
+            moved: false,
+        },
+    ];
+    let mut out = vec![];
+    json::write_hunk(&mut out, b"@@ -3,2 +3 @@\n", Some(&header), &lines).unwrap();
+    assert_eq!(
+        r#"{"type":"hunk","header":"@@ -3,2 +3 @@","old":[[3,2]],"new":[3,1],"lines":[{"kind":"removed","text":"a \"b\"","refined":[[2,5]],"shared":[[0,2]]},{"kind":"context","text":"\tc"}]}
+"#,
+        String::from_utf8_lossy(&out)
+    );
+}
+
+#[test]
+fn json_invalid_utf8_test() {
+    // the offsets are the ones of the replaced text
+    let lines = [json::Line {
+        kind: "added",
+        text: b"caf\xe9 foo bar",
+        segments: Some((vec![(9, 12)], vec![(0, 9)])),
+        moved: false,
+    }];
+    let mut out = vec![];
+    json::write_hunk(&mut out, b"@@ -0,0 +1 @@\n", None, &lines).unwrap();
+    assert_eq!(
+        r#"{"type":"hunk","header":"@@ -0,0 +1 @@","old":null,"new":null,"lines":[{"kind":"added","text":"caf� foo bar","refined":[[11,14]],"shared":[[0,11]]}]}
+"#,
+        String::from_utf8_lossy(&out)
+    );
+}
+
+#[test]
+fn side_by_side_block_test() {
+    let header = HunkHeader::new(vec![(8, 3)], (9, 3));
+    let mut layout = side_by_side::SideBySide::new(Some(&header), 23, 1, 8, None);
+    let styled = |text: &str| {
+        let mut line = wrap::StyledLine::default();
+        output(
+            text.as_bytes(),
+            0,
+            text.len(),
+            &ColorSpec::default(),
+            &mut line,
+        )
+        .unwrap();
+        line
+    };
+    let old = vec![styled("-a"), styled("-b")];
+    let new = vec![styled("+x"), styled("+b'"), styled("+c")];
+    let mut out = termcolor::Buffer::no_color();
+    side_by_side::write_block(&mut layout, &old, &new, &[(1, 1)], &mut out).unwrap();
+    layout
+        .write_row(
+            Some(&styled(" context")),
+            Some(&styled(" context")),
+            &mut out,
+        )
+        .unwrap();
+    assert_eq!(
+        " 8 -a      │  9 +x
+ 9 -b      │ 10 +b'
+           │ 11 +c
+10         │ 12  
+   context │    context
+",
+        String::from_utf8_lossy(out.as_slice())
+    );
+}
+
+#[test]
+fn html_writer_test() {
+    let config = AppConfig::default();
+    let mut fragment = vec![];
+    let mut out = html::HtmlColorWriter::new(&mut fragment, &config, HtmlMode::Fragment).unwrap();
+    out.start_file().unwrap();
+    output(b"+a<b\n", 0, 5, &config.added_face, &mut out).unwrap();
+    output(b"", 0, 0, &config.removed_face, &mut out).unwrap();
+    let spec = color_spec(Some(Color::Ansi256(196)), Some(Color::Rgb(1, 2, 3)), true);
+    output(b"x & y", 0, 5, &spec, &mut out).unwrap();
+    out.finish().unwrap();
+    assert_eq!(
+        "<pre class=\"diffr\"><a id=\"file-1\"></a><span class=\"added\">+a&lt;b</span>\n\
+         <span style=\"color: #ff0000; background-color: #010203; font-weight: bold;\">\
+         x &amp; y</span></pre>\n",
+        String::from_utf8_lossy(&fragment)
+    );
+
+    let mut document = vec![];
+    html::HtmlColorWriter::new(&mut document, &config, HtmlMode::Document).unwrap();
+    let document = String::from_utf8_lossy(&document);
+    assert!(document.contains(
+        "pre.diffr .refine-added { color: #e5e5e5; background-color: #00cd00; font-weight: bold; }"
+    ));
+    assert!(document.ends_with("</head>\n<body>\n<pre class=\"diffr\">"));
+}
+
+#[test]
+fn ignore_whitespace_test() {
+    let test = |expected: &[&str], mode: IgnoreWhitespace, line: &[u8]| {
+        let mut tokens = vec![(0, 1)];
+        WordTokenizer.tokenize(line, 1, &mut tokens);
+        mode.filter(line, &mut tokens, 1);
+        let tokens = tokens[1..]
+            .iter()
+            .map(|&(lo, hi)| String::from_utf8_lossy(&line[lo..hi]))
+            .collect::<Vec<_>>();
+        assert_eq!(expected, &tokens[..]);
+    };
+    let line = b"+ \ta  b\t \n";
+    test(&["a", "b", "\n"], IgnoreWhitespace::All, line);
+    test(
+        &[" \t", "a", "  ", "b", "\t ", "\n"],
+        IgnoreWhitespace::Amount,
+        line,
+    );
+    test(&["a", "  ", "b", "\n"], IgnoreWhitespace::Ends, line);
+}
+
+#[test]
+fn file_header_parser_test() {
+    use file_header::{FileHeader, FileStatus};
+    let parse = |lines: &[&str]| {
+        let mut parser = FileHeaderParser::default();
+        for line in lines {
+            assert!(parser.push(line.as_bytes()), "{}", line);
+        }
+        parser.header().map(|h| {
+            (
+                h.old_path
+                    .as_ref()
+                    .map(|p| String::from_utf8_lossy(p).into_owned()),
+                h.new_path
+                    .as_ref()
+                    .map(|p| String::from_utf8_lossy(p).into_owned()),
+                h.status,
+            )
+        })
+    };
+    let some = |s: &str| Some(s.to_string());
+    assert_eq!(
+        Some((some("src/a b.rs"), some("src/a b.rs"), FileStatus::Modified)),
+        parse(&[
+            "diff --git a/src/a b.rs b/src/a b.rs\n",
+            "index 111..222 100644\n",
+        ])
+    );
+    assert_eq!(
+        Some((some("old.rs"), some("new.rs"), FileStatus::Renamed)),
+        parse(&[
+            "\x1b[1mdiff --git a/old.rs b/new.rs\x1b[m\n",
+            "similarity index 90%\n",
+            "rename from old.rs\n",
+            "rename to new.rs\n",
+        ])
+    );
+    assert_eq!(
+        Some((None, some("new.rs"), FileStatus::Added)),
+        parse(&[
+            "diff --git a/new.rs b/new.rs\n",
+            "new file mode 100644\n",
+            "--- /dev/null\n",
+            "+++ b/new.rs\n",
+        ])
+    );
+    assert_eq!(
+        Some((some("a/x.c"), some("b/x.c"), FileStatus::Modified)),
+        parse(&[
+            "--- a/x.c\t2020-01-01 00:00:00\n",
+            "+++ b/x.c\t2020-01-02 00:00:00\n",
+        ])
+    );
+    assert_eq!(
+        Some((some("old.c"), some("new.c"), FileStatus::Modified)),
+        parse(&[
+            "*** old.c\t2020-01-01 00:00:00\n",
+            "--- new.c\t2020-01-02 00:00:00\n",
+        ])
+    );
+
+    let mut parser = FileHeaderParser::default();
+    assert!(!parser.push(b"commit 1234\n"));
+    assert!(parser.push(b"diff --git a/x b/x\n"));
+    assert!(!parser.push(b"@@ -1 +1 @@\n"));
+    assert!(!parser.push(b"diff --git a/y b/y\n"));
+    let mut context = FileHeaderParser::default();
+    assert!(!context.push(b"***************\n"));
+    assert!(!context.push(b"*** 1,3 ****\n"));
+    assert!(context.header().is_none());
+    assert_eq!(
+        Some(&FileHeader {
+            old_path: Some(b"x".to_vec()),
+            new_path: Some(b"x".to_vec()),
+            ..Default::default()
+        }),
+        parser.header()
+    );
+}
+
+#[test]
+fn similarity_threshold_test() {
+    // 5 of the 7 tokens of each line are shared: a similarity of 5/7
+    let data = b"a b c d\na b x y";
+    let mut tokens = vec![];
+    diffr_lib::tokenize(data, 0, &mut tokens);
+    let (removed, added): (Vec<_>, Vec<_>) = tokens
+        .into_iter()
+        .filter(|&(lo, hi)| &data[lo..hi] != b"\n")
+        .partition(|&(lo, _)| lo < 7);
+    let m = TokenMap::new(
+        &mut [(removed.iter(), &data[..]), (added.iter(), &data[..])],
+        TokenNormalization::default(),
+    );
+    let refine = |similarity_threshold| {
+        let config = AppConfig {
+            similarity_threshold,
+            ..AppConfig::default()
+        };
+        let mut refinement = Refinement::new(&config);
+        let mut stats = ExecStats::new(false);
+        refinement.refine(data, &m, &removed, &added, &mut stats);
+        (refinement.shared_removed, refinement.shared_added)
+    };
+    assert_eq!((vec![(0, 4), (5, 6)], vec![(8, 12), (13, 14)]), refine(0.7));
+    assert_eq!((vec![(0, 7)], vec![(8, 15)]), refine(0.72));
+}
+
+#[test]
+fn unified_diff_test() {
+    let diff = |old: &str, new: &str, context| {
+        let diff = compare::unified_diff(old.as_bytes(), new.as_bytes(), b"a", b"b", context);
+        String::from_utf8(diff.unwrap()).unwrap()
+    };
+    assert_eq!("", diff("a\nb\n", "a\nb\n", 3));
+    assert_eq!(
+        "--- a\n+++ b\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n",
+        diff("a\nb\nc\n", "a\nB\nc\n", 3)
+    );
+    assert_eq!(
+        "--- a\n+++ b\n@@ -2 +2 @@\n-b\n+B\n",
+        diff("a\nb\nc\n", "a\nB\nc\n", 0)
+    );
+    assert_eq!("--- a\n+++ b\n@@ -0,0 +1 @@\n+a\n", diff("", "a\n", 3));
+    assert_eq!(
+        "--- a\n+++ b\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n",
+        diff("a\nb", "a\nb\n", 3)
+    );
+    // changes far from each other are in separate hunks
+    let old = "1\n2\n3\n4\n5\n6\n7\n8\n";
+    let new = "0\n2\n3\n4\n5\n6\n7\n9\n";
+    assert_eq!(
+        "--- a\n+++ b\n@@ -1,2 +1,2 @@\n-1\n+0\n 2\n@@ -7,2 +7,2 @@\n 7\n-8\n+9\n",
+        diff(old, new, 1)
+    );
+    assert_eq!(
+        "--- a\n+++ b\n@@ -1,8 +1,8 @@\n-1\n+0\n 2\n 3\n 4\n 5\n 6\n 7\n-8\n+9\n",
+        diff(old, new, 3)
+    );
+    assert_eq!("Binary files a and b differ\n", diff("\0", "a", 3));
+}
+
+#[test]
+fn diff_directories_test() {
+    let root = std::env::temp_dir().join(format!("diffr-test-{}", std::process::id()));
+    let (old, new) = (root.join("old"), root.join("new"));
+    for (dir, path, content) in &[
+        (&old, "same", "a\n"),
+        (&new, "same", "a\n"),
+        (&old, "sub/changed", "a\nb\n"),
+        (&new, "sub/changed", "a\nc\n"),
+        (&old, "removed", "r\n"),
+        (&new, "sub/added", "n\n"),
+    ] {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+    let diff = compare::diff_directories(&old, &new, 3);
+    std::fs::remove_dir_all(&root).unwrap();
+    assert_eq!(
+        "diff --git a/removed b/removed\n\
+         deleted file mode 100644\n\
+         --- a/removed\n\
+         +++ /dev/null\n\
+         @@ -1 +0,0 @@\n\
+         -r\n\
+         diff --git a/sub/added b/sub/added\n\
+         new file mode 100644\n\
+         --- /dev/null\n\
+         +++ b/sub/added\n\
+         @@ -0,0 +1 @@\n\
+         +n\n\
+         diff --git a/sub/changed b/sub/changed\n\
+         --- a/sub/changed\n\
+         +++ b/sub/changed\n\
+         @@ -1,2 +1,2 @@\n \
+         a\n\
+         -b\n\
+         +c\n",
+        String::from_utf8(diff.unwrap()).unwrap()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn diff_directories_links_test() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    let root = std::env::temp_dir().join(format!("diffr-test-links-{}", std::process::id()));
+    let (old, new) = (root.join("old"), root.join("new"));
+    std::fs::create_dir_all(&old).unwrap();
+    std::fs::create_dir_all(&new).unwrap();
+    // a name which is not valid UTF-8, and a link to its own directory
+    std::fs::write(new.join(OsStr::from_bytes(b"caf\xe9")), "n\n").unwrap();
+    std::os::unix::fs::symlink(".", new.join("loop")).unwrap();
+    let diff = compare::diff_directories(&old, &new, 3);
+    std::fs::remove_dir_all(&root).unwrap();
+    assert_eq!(
+        &b"diff --git a/caf\xe9 b/caf\xe9\n\
+           new file mode 100644\n\
+           --- /dev/null\n\
+           +++ b/caf\xe9\n\
+           @@ -0,0 +1 @@\n\
+           +n\n\
+           diff --git a/loop b/loop\n\
+           new file mode 120000\n\
+           --- /dev/null\n\
+           +++ b/loop\n\
+           @@ -0,0 +1 @@\n\
+           +.\n\
+           \\ No newline at end of file\n"[..],
+        &diff.unwrap()[..]
+    );
+}
@@ -1,4 +1,5 @@
 use std::env;
+use std::io::Write;
 use std::path::PathBuf;
 use std::process::{Command, Stdio};
 use StringTest::*;
@@ -73,6 +74,12 @@ fn diffr_path() -> PathBuf {
 }
 
 fn test_cli(descr: ProcessTest) {
+    test_cli_input(descr, &[], "")
+}
+
+/// Like `test_cli`, with `input` written to the standard input of diffr,
+/// and the variables `env` set in its environment.
+fn test_cli_input(descr: ProcessTest, env: &[(&str, &str)], input: &str) {
     let mut cmd = Command::new(diffr_path());
     cmd.stdout(Stdio::piped());
     cmd.stderr(Stdio::piped());
@@ -80,7 +87,16 @@ fn test_cli(descr: ProcessTest) {
     for arg in descr.args {
         cmd.arg(&*arg);
     }
-    let child = cmd.spawn().expect("spawn");
+    for (key, value) in env {
+        cmd.env(key, value);
+    }
+    let mut child = cmd.spawn().expect("spawn");
+    child
+        .stdin
+        .take()
+        .expect("stdin")
+        .write_all(input.as_bytes())
+        .expect("write_all");
     let output = child.wait_with_output().expect("wait_with_output");
     fn string_of_status(code: bool) -> &'static str {
         if code {
@@ -120,7 +136,7 @@ fn color_invalid_face_name() {
     test_cli(ProcessTest {
         args: &["--colors", "notafacename"],
         out: Empty,
-        err: Exactly("unexpected face name: got 'notafacename', expected added|refine-added|removed|refine-removed"),
+        err: Exactly("unexpected face name: got 'notafacename', expected added|refine-added|removed|refine-removed|moved-added|moved-removed|file-header|file-header-info|line-number"),
         is_success: false,
     })
 }
@@ -242,3 +258,505 @@ fn line_numbers_style() {
         is_success: false,
     });
 }
+
+#[test]
+fn granularity() {
+    // ok
+    test_cli(ProcessTest {
+        args: &["--granularity", "char"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--granularity", "word"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--granularity", "subword"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--granularity", "whitespace"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+
+    // fail
+    test_cli(ProcessTest {
+        args: &["--granularity", "foo"],
+        out: Empty,
+        err: Exactly("unexpected granularity: got 'foo', expected char|word|subword|whitespace"),
+        is_success: false,
+    });
+}
+
+#[test]
+fn refine_scope() {
+    test_cli(ProcessTest {
+        args: &["--refine-scope", "hunk"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--refine-scope", "lines"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--refine-scope", "foo"],
+        out: Empty,
+        err: Exactly("unexpected refine scope: got 'foo', expected hunk|lines"),
+        is_success: false,
+    });
+}
+
+#[test]
+fn similarity_threshold() {
+    test_cli(ProcessTest {
+        args: &["--similarity-threshold", "0.5"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--similarity-threshold", "1"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--similarity-threshold", "1.5"],
+        out: Empty,
+        err: Exactly(
+            "unexpected similarity threshold: got '1.5', expected a number between 0 and 1",
+        ),
+        is_success: false,
+    });
+    test_cli(ProcessTest {
+        args: &["--similarity-threshold", "foo"],
+        out: Empty,
+        err: Exactly(
+            "unexpected similarity threshold: got 'foo', expected a number between 0 and 1",
+        ),
+        is_success: false,
+    });
+}
+
+#[test]
+fn algorithm() {
+    test_cli(ProcessTest {
+        args: &["--algorithm", "myers"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--algorithm", "patience"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--algorithm", "histogram"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--algorithm", "foo"],
+        out: Empty,
+        err: Exactly("unexpected algorithm: got 'foo', expected myers|patience|histogram"),
+        is_success: false,
+    });
+}
+
+#[test]
+fn limits() {
+    test_cli(ProcessTest {
+        args: &["--diff-cost-limit", "100"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--hunk-token-limit", "1000"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--diff-cost-limit", "0"],
+        out: Empty,
+        err: Exactly(
+            "unexpected limit: got '0' for --diff-cost-limit, expected a positive integer",
+        ),
+        is_success: false,
+    });
+    test_cli(ProcessTest {
+        args: &["--hunk-token-limit", "foo"],
+        out: Empty,
+        err: Exactly(
+            "unexpected limit: got 'foo' for --hunk-token-limit, expected a positive integer",
+        ),
+        is_success: false,
+    });
+}
+
+#[test]
+fn hunk_token_limit() {
+    // 8 tokens: "foo", " ", "bar" and the newline of each line
+    let input = "@@ -1 +1 @@\n-foo bar\n+foo baz\n";
+    test_cli_input(
+        ProcessTest {
+            args: &["--markers", "--hunk-token-limit", "8"],
+            out: Exactly("@@ -1 +1 @@\n-foo [-bar-]\n+foo {+baz+}\n"),
+            err: Empty,
+            is_success: true,
+        },
+        &[],
+        input,
+    );
+    // the hunk over the limit is not refined
+    test_cli_input(
+        ProcessTest {
+            args: &["--markers", "--hunk-token-limit", "7"],
+            out: Exactly(input),
+            err: Empty,
+            is_success: true,
+        },
+        &[],
+        input,
+    );
+}
+
+#[test]
+fn ignore_whitespace() {
+    test_cli(ProcessTest {
+        args: &["--ignore-whitespace"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--ignore-whitespace=amount"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--ignore-whitespace=ends"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--ignore-whitespace=foo"],
+        out: Empty,
+        err: Exactly("unexpected ignore whitespace mode: got 'foo', expected all|amount|ends"),
+        is_success: false,
+    });
+}
+
+#[test]
+fn ignore_case() {
+    test_cli(ProcessTest {
+        args: &["--ignore-case"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+}
+
+#[test]
+fn detect_moved() {
+    test_cli(ProcessTest {
+        args: &["--detect-moved"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+}
+
+#[test]
+fn file_header() {
+    test_cli(ProcessTest {
+        args: &["--file-header=raw"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--file-header=banner"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--file-header=foo"],
+        out: Empty,
+        err: Exactly("unexpected file header style: got 'foo', expected raw|banner"),
+        is_success: false,
+    });
+}
+
+#[test]
+fn compare_files() {
+    test_cli(ProcessTest {
+        args: &["README.md", "README.md"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["-U", "1", "-", "README.md"],
+        out: AtLeast("+++ README.md"),
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["README.md"],
+        out: Empty,
+        err: Exactly("unexpected files: got 1 file(s), expected two files to compare"),
+        is_success: false,
+    });
+    test_cli(ProcessTest {
+        args: &["README.md", "does-not-exist"],
+        out: Empty,
+        err: AtLeast("does-not-exist"),
+        is_success: false,
+    });
+    test_cli(ProcessTest {
+        args: &["-r"],
+        out: Empty,
+        err: Exactly("unexpected files: got 0 file(s), expected two files to compare"),
+        is_success: false,
+    });
+    test_cli(ProcessTest {
+        args: &["-r", "src", "src"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    // the flags with an optional value do not take the files
+    for args in &[
+        &["--line-numbers", "README.md", "README.md"][..],
+        &["--line-numbers", "aligned", "README.md", "README.md"],
+        &["--ignore-whitespace", "README.md", "README.md"],
+        &["--file-header=banner", "README.md", "README.md"],
+        &["--wrap", "README.md", "README.md"],
+    ] {
+        test_cli(ProcessTest {
+            args,
+            out: Empty,
+            err: Empty,
+            is_success: true,
+        });
+    }
+    test_cli(ProcessTest {
+        args: &["-U", "x", "README.md", "README.md"],
+        out: Empty,
+        err: Exactly(
+            "unexpected number of context lines: got 'x', expected a non-negative integer",
+        ),
+        is_success: false,
+    });
+}
+
+#[test]
+fn side_by_side() {
+    test_cli(ProcessTest {
+        args: &["--side-by-side", "--width", "60", "README.md", "README.md"],
+        out: Empty,
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--side-by-side", "--width", "60", "-", "README.md"],
+        out: AtLeast(" │ "),
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--side-by-side", "--width", "0"],
+        out: Empty,
+        err: Exactly("unexpected width: got '0', expected a positive integer"),
+        is_success: false,
+    });
+}
+
+#[test]
+fn html() {
+    test_cli(ProcessTest {
+        args: &["--html=fragment"],
+        out: Exactly("<pre class=\"diffr\"></pre>"),
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--html-stylesheet", "diffr.css", "--html"],
+        out: AtLeast("<link rel=\"stylesheet\" type=\"text/css\" href=\"diffr.css\">"),
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--html=foo"],
+        out: Empty,
+        err: Exactly("unexpected html mode: got 'foo', expected document|fragment"),
+        is_success: false,
+    });
+    // the mode is optional: the files are not taken as a mode
+    test_cli(ProcessTest {
+        args: &["--html", "-", "README.md"],
+        out: AtLeast("<span class=\"added\">+</span>"),
+        err: Empty,
+        is_success: true,
+    });
+}
+
+#[test]
+fn wrap() {
+    test_cli(ProcessTest {
+        args: &["--wrap=20", "--wrap-glyph", "~", "-", "README.md"],
+        out: AtLeast("~"),
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--wrap=0"],
+        out: Empty,
+        err: Exactly("unexpected width: got '0', expected a positive integer"),
+        is_success: false,
+    });
+}
+
+#[test]
+fn tabs() {
+    test_cli(ProcessTest {
+        args: &["--tabs", "4", "--line-numbers=aligned", "-", "README.md"],
+        out: AtLeast("@@"),
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--tabs", "x"],
+        out: Empty,
+        err: Exactly("unexpected tab width: got 'x', expected a positive integer"),
+        is_success: false,
+    });
+}
+
+#[test]
+fn color_when() {
+    let input = "@@ -1 +1 @@\n\x1b[31m-foo bar\x1b[m\n\x1b[32m+foo baz\x1b[m\n";
+    let plain = "@@ -1 +1 @@\n-foo bar\n+foo baz\n";
+    test_cli_input(
+        ProcessTest {
+            args: &["--color=never"],
+            out: Exactly("@@ -1 +1 @@\n-foo [-bar-]\n+foo {+baz+}\n"),
+            err: Empty,
+            is_success: true,
+        },
+        &[],
+        input,
+    );
+    for env in &[("NO_COLOR", "1"), ("TERM", "dumb")] {
+        test_cli_input(
+            ProcessTest {
+                args: &[],
+                out: Exactly(plain),
+                err: Empty,
+                is_success: true,
+            },
+            &[*env],
+            input,
+        );
+        test_cli_input(
+            ProcessTest {
+                args: &["--color=always"],
+                out: AtLeast("\x1b[42mbaz\x1b[0m"),
+                err: Empty,
+                is_success: true,
+            },
+            &[*env],
+            input,
+        );
+    }
+    test_cli(ProcessTest {
+        args: &["--color", "sometimes"],
+        out: Empty,
+        err: Exactly("unexpected color mode: got 'sometimes', expected auto|always|never"),
+        is_success: false,
+    });
+}
+
+#[test]
+fn markers() {
+    let input = "@@ -1 +1 @@\n-let x = foo(bar);\n+let x = baz(bar);\n";
+    test_cli_input(
+        ProcessTest {
+            args: &["--markers", "--added-markers", "<ins>", "</ins>"],
+            out: Exactly("@@ -1 +1 @@\n-let x = [-foo-](bar);\n+let x = <ins>baz</ins>(bar);\n"),
+            err: Empty,
+            is_success: true,
+        },
+        &[],
+        input,
+    );
+    test_cli_input(
+        ProcessTest {
+            args: &["--removed-markers", "~~", "~~"],
+            out: Exactly("@@ -1 +1 @@\n-let x = ~~foo~~(bar);\n+let x = {+baz+}(bar);\n"),
+            err: Empty,
+            is_success: true,
+        },
+        &[],
+        input,
+    );
+    test_cli_input(
+        ProcessTest {
+            args: &["--markers", "--color=always"],
+            out: AtLeast("\x1b[42m{+baz+}\x1b[0m"),
+            err: Empty,
+            is_success: true,
+        },
+        &[],
+        input,
+    );
+}
+
+#[test]
+fn format() {
+    test_cli(ProcessTest {
+        args: &["--format", "json", "-", "README.md"],
+        out: AtLeast("\"new\":[1,"),
+        err: Empty,
+        is_success: true,
+    });
+    test_cli(ProcessTest {
+        args: &["--format", "yaml"],
+        out: Empty,
+        err: Exactly("unexpected output format: got 'yaml', expected text|json"),
+        is_success: false,
+    });
+    for args in &[
+        &["--format", "json", "--html"][..],
+        &["--format", "json", "--side-by-side"],
+        &["--format", "json", "--markers"],
+        &["--format", "json", "--wrap"],
+    ] {
+        test_cli(ProcessTest {
+            args,
+            out: Empty,
+            err: AtLeast("cannot be used with"),
+            is_success: false,
+        });
+    }
+}
@@ -0,0 +1,258 @@
+//! Wrapping of the lines too long for the width of the output, for
+//! `--wrap` and `--side-by-side`, and expansion of their tabs.
+
+use std::io::{self, Write};
+use std::str;
+use termcolor::{ColorSpec, WriteColor};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use super::diffr_lib::{is_blank, Span, Tokenizer, WordTokenizer};
+use super::output;
+
+/// A line painted in memory, to be wrapped: its text, without the
+/// newline, and the start of each segment of text with its face.
+#[derive(Default)]
+pub struct StyledLine {
+    text: Vec<u8>,
+    faces: Vec<(usize, ColorSpec)>,
+    current: ColorSpec,
+    /// Whether the line ended with a newline.
+    newline: bool,
+}
+
+impl StyledLine {
+    /// The face of the start of the line.
+    pub fn first_face(&self) -> ColorSpec {
+        self.faces
+            .first()
+            .map_or(ColorSpec::default(), |(_, face)| face.clone())
+    }
+}
+
+impl Write for StyledLine {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &b in buf {
+            if b == b'\n' {
+                self.newline = true;
+                continue;
+            }
+            if self.faces.last().map(|(_, face)| face) != Some(&self.current) {
+                self.faces.push((self.text.len(), self.current.clone()));
+            }
+            self.text.push(b);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WriteColor for StyledLine {
+    fn supports_color(&self) -> bool {
+        true
+    }
+
+    fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
+        self.current = spec.clone();
+        Ok(())
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        self.current = ColorSpec::default();
+        Ok(())
+    }
+}
+
+/// A row of a wrapped line: segments of text with their face, taking
+/// `len` columns of the terminal.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Cell {
+    pub segments: Vec<(Vec<u8>, ColorSpec)>,
+    pub len: usize,
+}
+
+impl Cell {
+    fn push(&mut self, column: &Column) {
+        match self.segments.last_mut() {
+            Some((text, last)) if last == column.face => text.extend_from_slice(column.bytes),
+            _ => self
+                .segments
+                .push((column.bytes.to_vec(), column.face.clone())),
+        }
+        self.len += column.width;
+    }
+
+    /// Write the segments of the row.
+    pub fn write(&self, out: &mut impl WriteColor) -> io::Result<()> {
+        for (text, face) in &self.segments {
+            output(text, 0, text.len(), face, out)?;
+        }
+        Ok(())
+    }
+}
+
+/// One character of a line once its tabs are expanded, taking `width`
+/// columns of the terminal: 2 for wide characters (CJK, ...), 0 for
+/// combining marks.
+struct Column<'a> {
+    bytes: &'a [u8],
+    face: &'a ColorSpec,
+    width: usize,
+    /// Whether the line may be broken before the column: a token other
+    /// than whitespace starts there.
+    breakable: bool,
+}
+
+/// The characters of `line`, with its tabs expanded to the next
+/// multiple of `tab_stop`. The tab stops start after the first
+/// `prefix_len` columns, the `+` and `-` of the diff.
+fn columns<'a>(
+    line: &'a StyledLine,
+    prefix_len: usize,
+    tab_stop: usize,
+    default: &'a ColorSpec,
+) -> Vec<Column<'a>> {
+    let mut tokens: Vec<Span> = vec![];
+    WordTokenizer.tokenize(&line.text, 0, &mut tokens);
+    let mut token_starts = tokens
+        .iter()
+        .filter(|&&(lo, hi)| !is_blank(&line.text[lo..hi]))
+        .map(|&(lo, _)| lo)
+        .peekable();
+    let mut faces = line.faces.iter().peekable();
+    let mut face = default;
+    let mut columns = vec![];
+    // the width of the columns so far
+    let mut width = 0;
+    let mut i = 0;
+    while i < line.text.len() {
+        while let Some((_, next)) = faces.next_if(|(start, _)| *start <= i) {
+            face = next;
+        }
+        let mut breakable = false;
+        while token_starts.next_if(|&start| start <= i).is_some() {
+            breakable = true;
+        }
+        // one character: a byte followed by its continuation bytes
+        let len = 1 + line.text[i + 1..]
+            .iter()
+            .take_while(|&&b| b & 0xc0 == 0x80)
+            .count();
+        let bytes = &line.text[i..i + len];
+        match bytes {
+            b"\t" if prefix_len <= width => {
+                let nb_spaces = tab_stop - (width - prefix_len) % tab_stop;
+                for _ in 0..nb_spaces {
+                    columns.push(Column {
+                        bytes: b" ",
+                        face,
+                        width: 1,
+                        breakable: false,
+                    });
+                }
+                width += nb_spaces;
+            }
+            _ => {
+                // invalid UTF-8 and control characters take one column
+                let char_width = str::from_utf8(bytes)
+                    .ok()
+                    .and_then(|c| c.chars().next())
+                    .and_then(UnicodeWidthChar::width)
+                    .unwrap_or(1);
+                columns.push(Column {
+                    bytes,
+                    face,
+                    width: char_width,
+                    breakable,
+                });
+                width += char_width;
+            }
+        }
+        i += len;
+    }
+    columns
+}
+
+/// Split `line` into rows of at most `first_width` columns for the
+/// first one, and `width` columns for the next ones, after expanding its
+/// tabs. Rows are broken at the start of a token when possible. The tab
+/// stops are every `tab_stop` columns after the first `prefix_len` ones.
+/// A character wider than a row gets a row of its own.
+pub fn wrap(
+    line: &StyledLine,
+    prefix_len: usize,
+    tab_stop: usize,
+    first_width: usize,
+    width: usize,
+) -> Vec<Cell> {
+    let default = ColorSpec::default();
+    let columns = columns(line, prefix_len, tab_stop, &default);
+    let mut cells = vec![];
+    let mut start = 0;
+    loop {
+        let width = if cells.is_empty() { first_width } else { width }.max(1);
+        let mut end = start;
+        let mut row_width = 0;
+        while end < columns.len() && row_width + columns[end].width <= width {
+            row_width += columns[end].width;
+            end += 1;
+        }
+        if end == start {
+            end += 1;
+        } else if end < columns.len() {
+            // break before the last token starting in the row
+            end = (start + 1..=end)
+                .rev()
+                .find(|&k| columns[k].breakable)
+                .unwrap_or(end);
+        }
+        let mut cell = Cell::default();
+        for column in &columns[start..end] {
+            cell.push(column);
+        }
+        cells.push(cell);
+        if end == columns.len() {
+            return cells;
+        }
+        start = end;
+    }
+}
+
+/// Write `line` on rows of at most `width` columns, after a margin of
+/// `margin_width` columns already written for the first row. The next
+/// rows start with an empty margin and the continuation `glyph`, padded
+/// to the `prefix_len` columns of the `+` and `-` of the diff. Tabs are
+/// expanded to the tab stops every `tab_stop` columns.
+pub fn write_wrapped(
+    line: &StyledLine,
+    prefix_len: usize,
+    tab_stop: usize,
+    margin_width: usize,
+    glyph: &str,
+    width: usize,
+    out: &mut impl WriteColor,
+) -> io::Result<()> {
+    let glyph = format!("{:w$}", glyph, w = prefix_len);
+    let glyph_width = UnicodeWidthStr::width(glyph.as_str());
+    let first_width = width.saturating_sub(margin_width);
+    let cells = wrap(
+        line,
+        prefix_len,
+        tab_stop,
+        first_width,
+        first_width.saturating_sub(glyph_width),
+    );
+    for (k, cell) in cells.iter().enumerate() {
+        if k != 0 {
+            write!(out, "{:w$}", "", w = margin_width)?;
+            output(glyph.as_bytes(), 0, glyph.len(), &line.first_face(), out)?;
+        }
+        cell.write(out)?;
+        if k + 1 != cells.len() || line.newline {
+            out.write_all(b"\n")?;
+        }
+    }
+    Ok(())
+}